@@ -0,0 +1,190 @@
+//! Serialized access through a thread that owns the value.
+//!
+//! A `SharedActor` moves its value onto a dedicated thread and runs submitted closures there one
+//! at a time, in the order they were submitted. Callers never hold a lock, so a long critical
+//! section delays the replies queued behind it but never blocks the thread that submitted it.
+//! This suits state such as a connection or a cache that is rebuilt in place, where readers would
+//! otherwise sit behind a writer for the whole update.
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::task::{
+    Context,
+    Poll,
+    Waker,
+};
+
+use crate::deps::parking_lot::{
+    Condvar,
+    Mutex,
+};
+
+type Job<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// A value owned by its own thread. Clones submit to the same thread, which exits and drops the
+/// value once the last clone is gone and every submitted closure has run.
+pub struct SharedActor<T> {
+    jobs: mpsc::Sender<Job<T>>,
+}
+
+impl<T: Send + 'static> SharedActor<T> {
+    pub fn new(init: T) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job<T>>();
+        std::thread::Builder::new()
+            .name(format!("SharedActor<{}>", std::any::type_name::<T>()))
+            .spawn(move || {
+                let mut value = init;
+                for job in queue {
+                    job(&mut value);
+                }
+            })
+            .expect("failed to spawn the SharedActor thread");
+        SharedActor { jobs }
+    }
+
+    /// Queues `f` to run with exclusive access to the value and returns a handle to its result.
+    ///
+    /// If `f` panics the actor keeps running and the panic is resumed by whoever waits on the
+    /// reply. Any changes `f` made before panicking are kept.
+    pub fn call<R>(&self, f: impl FnOnce(&mut T) -> R + Send + 'static) -> Reply<R>
+    where
+        R: Send + 'static,
+    {
+        let reply = Arc::new(Slot {
+            outcome: Mutex::new(Outcome {
+                result: None,
+                waker: None,
+            }),
+            ready: Condvar::new(),
+        });
+        let slot = reply.clone();
+        let job: Job<T> = Box::new(move |value| {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(value)));
+            slot.complete(result);
+        });
+        self.jobs
+            .send(job)
+            .unwrap_or_else(|_| unreachable!("the SharedActor thread outlives its senders"));
+        Reply(reply)
+    }
+}
+
+impl<T> Clone for SharedActor<T> {
+    fn clone(&self) -> Self {
+        SharedActor {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for SharedActor<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedActor").finish_non_exhaustive()
+    }
+}
+
+struct Outcome<R> {
+    result: Option<std::thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+struct Slot<R> {
+    outcome: Mutex<Outcome<R>>,
+    ready: Condvar,
+}
+
+impl<R> Slot<R> {
+    fn complete(&self, result: std::thread::Result<R>) {
+        let waker = {
+            let mut outcome = self.outcome.lock();
+            outcome.result = Some(result);
+            outcome.waker.take()
+        };
+        self.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The pending result of `SharedActor::call`. Either block on it with `wait` or await it.
+pub struct Reply<R>(Arc<Slot<R>>);
+
+impl<R> Reply<R> {
+    /// Blocks until the closure has run and returns its result.
+    pub fn wait(self) -> R {
+        let mut outcome = self.0.outcome.lock();
+        loop {
+            if let Some(result) = outcome.result.take() {
+                return unwrap_result(result);
+            }
+            self.0.ready.wait(&mut outcome);
+        }
+    }
+
+    /// Whether the closure has run.
+    pub fn is_ready(&self) -> bool {
+        self.0.outcome.lock().result.is_some()
+    }
+}
+
+fn unwrap_result<R>(result: std::thread::Result<R>) -> R {
+    match result {
+        Ok(value) => value,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+impl<R> Future for Reply<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut outcome = self.0.outcome.lock();
+        match outcome.result.take() {
+            Some(result) => Poll::Ready(unwrap_result(result)),
+            None => {
+                outcome.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> std::fmt::Debug for Reply<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Reply").field("ready", &self.is_ready()).finish()
+    }
+}
+
+#[test]
+fn test_actor_runs_calls_in_order() {
+    use std::pin::pin;
+
+    let actor = SharedActor::new(Vec::new());
+    let replies: Vec<_> = (0..10u32)
+        .map(|i| {
+            actor.call(move |log: &mut Vec<u32>| {
+                log.push(i);
+                log.len()
+            })
+        })
+        .collect();
+    let lengths: Vec<_> = replies.into_iter().map(Reply::wait).collect();
+    assert_eq!(lengths, (1..=10).collect::<Vec<_>>());
+
+    let other = actor.clone();
+    let failed = std::panic::catch_unwind(|| other.call(|_| panic!("boom")).wait());
+    assert!(failed.is_err());
+
+    let mut reply = pin!(actor.call(|log| log.iter().sum::<u32>()));
+    let mut cx = Context::from_waker(Waker::noop());
+    let total = loop {
+        if let Poll::Ready(total) = reply.as_mut().poll(&mut cx) {
+            break total;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(total, 45);
+}
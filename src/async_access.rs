@@ -0,0 +1,151 @@
+//! Async access traits, so service code can be generic over any shared state that can be read
+//! and written from async code.
+//!
+//! Access is scoped to a closure rather than handing out a guard, so a guard can never be held
+//! across an `.await`. The closure runs when the returned future is first polled. The blocking
+//! backends implement the traits by taking the lock there and completing at once; a backend with
+//! an async lock would wait for the lock instead.
+//!
+//! `SharedRead` and `SharedWrite` return `Send` futures, so generic callers can spawn them on a
+//! multi-threaded executor, and are implemented by the pointers that can be shared across
+//! threads. `LocalSharedRead` and `LocalSharedWrite` are the same for every backend, including
+//! `rc_refcell`, without the `Send` bound.
+use std::future::Future;
+
+pub trait SharedRead<T: ?Sized> {
+    /// Runs `f` with shared access to the value.
+    fn with_read<R>(&self, f: impl FnOnce(&T) -> R + Send) -> impl Future<Output = R> + Send;
+}
+
+pub trait SharedWrite<T: ?Sized>: SharedRead<T> {
+    /// Runs `f` with exclusive access to the value. Releasing the access has the same effects as
+    /// releasing a `write()` guard.
+    fn with_write<R>(
+        &self,
+        f: impl FnOnce(&mut T) -> R + Send,
+    ) -> impl Future<Output = R> + Send;
+}
+
+/// `SharedRead` for state that may be confined to one thread.
+pub trait LocalSharedRead<T: ?Sized> {
+    fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> impl Future<Output = R>;
+}
+
+/// `SharedWrite` for state that may be confined to one thread.
+pub trait LocalSharedWrite<T: ?Sized>: LocalSharedRead<T> {
+    fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> impl Future<Output = R>;
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+macro_rules! impl_async_access {
+    ($($backend:ident),*) => {
+        $(
+            impl<T: ?Sized> SharedRead<T> for crate::$backend::SharedPtr<T>
+            where
+                Self: Sync,
+            {
+                fn with_read<R>(
+                    &self,
+                    f: impl FnOnce(&T) -> R + Send,
+                ) -> impl Future<Output = R> + Send {
+                    async move { f(&self.read()) }
+                }
+            }
+
+            impl<T: ?Sized> SharedWrite<T> for crate::$backend::SharedPtr<T>
+            where
+                Self: Sync,
+            {
+                fn with_write<R>(
+                    &self,
+                    f: impl FnOnce(&mut T) -> R + Send,
+                ) -> impl Future<Output = R> + Send {
+                    async move { f(&mut self.write()) }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_local_access {
+    ($($backend:ident),*) => {
+        $(
+            impl<T: ?Sized> LocalSharedRead<T> for crate::$backend::SharedPtr<T> {
+                fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> impl Future<Output = R> {
+                    async move { f(&self.read()) }
+                }
+            }
+
+            impl<T: ?Sized> LocalSharedWrite<T> for crate::$backend::SharedPtr<T> {
+                fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> impl Future<Output = R> {
+                    async move { f(&mut self.write()) }
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "rc")]
+impl_local_access!(rc_refcell);
+#[cfg(feature = "mutex")]
+impl_async_access!(arc_mutex);
+#[cfg(feature = "mutex")]
+impl_local_access!(arc_mutex);
+#[cfg(feature = "rwlock")]
+impl_async_access!(arc_rwlock);
+#[cfg(feature = "rwlock")]
+impl_local_access!(arc_rwlock);
+
+#[test]
+#[cfg(all(feature = "rc", feature = "mutex", feature = "rwlock"))]
+fn test_async_access_is_generic_over_backends() {
+    use std::pin::pin;
+    use std::task::{
+        Context,
+        Poll,
+        Waker,
+    };
+
+    async fn bump(state: &impl LocalSharedWrite<u32>) -> u32 {
+        state.with_write(|count| *count += 1).await;
+        state.with_read(|count| *count).await
+    }
+
+    async fn bump_owned(state: impl SharedWrite<u32>) -> u32 {
+        state.with_write(|count| *count += 1).await;
+        state.with_read(|count| *count).await
+    }
+
+    // Only compiles because the futures of any `SharedWrite` are `Send`.
+    fn bump_spawnable<S>(state: S) -> impl Future<Output = u32> + Send
+    where
+        S: SharedWrite<u32> + Send,
+    {
+        bump_owned(state)
+    }
+
+    fn run<R>(future: impl Future<Output = R>) -> R {
+        let mut cx = Context::from_waker(Waker::noop());
+        match pin!(future).poll(&mut cx) {
+            Poll::Ready(value) => value,
+            Poll::Pending => panic!("blocking backends complete immediately"),
+        }
+    }
+
+    assert_eq!(run(bump(&crate::rc_refcell::SharedPtr::new(0))), 1);
+    assert_eq!(run(bump(&crate::arc_mutex::SharedPtr::new(1))), 2);
+    assert_eq!(run(bump(&crate::arc_rwlock::SharedPtr::new(2))), 3);
+    assert_eq!(run(bump_spawnable(crate::arc_mutex::SharedPtr::new(3))), 4);
+    let state = crate::arc_rwlock::SharedPtr::new(4);
+    let bumped = std::thread::spawn({
+        let future = bump_spawnable(state.clone());
+        move || run(future)
+    });
+    assert_eq!(bumped.join().unwrap(), 5);
+
+    // Nothing runs until the future is polled.
+    let write = LocalSharedWrite::with_write(&state, |count| *count += 1);
+    assert_eq!(*state.read(), 5);
+    run(write);
+    assert_eq!(*state.read(), 6);
+}
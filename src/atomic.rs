@@ -0,0 +1,197 @@
+//! Lock-free shared primitives with the `read()`/`write()` surface of `SharedPtr`.
+//!
+//! `SharedAtomicU64` and friends wrap an `Arc<AtomicU64>`, so a counter that started life as a
+//! `SharedPtr<u64>` can become lock-free by changing its type. `read()` keeps working. There is
+//! no `write()`, since an atomic can't be borrowed mutably without a lock: `*ptr.write() += 1`
+//! becomes `ptr.fetch_add(1)`, and other read-modify-writes become `ptr.update(|v| ...)`. Every
+//! operation uses `SeqCst` ordering.
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicI8,
+    AtomicIsize,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicU8,
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::Arc;
+
+/// A copy of the value at the time of `read()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AtomicReadGuard<T>(T);
+
+impl<T> std::ops::Deref for AtomicReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+macro_rules! define_shared_atomic {
+    ($name:ident, $atomic:ident, $value:ty) => {
+        #[doc = concat!("A lock-free shared `", stringify!($value), "`. See the module docs.")]
+        #[derive(Debug, Default)]
+        pub struct $name(Arc<$atomic>);
+
+        impl $name {
+            pub fn new(init: $value) -> Self {
+                $name(Arc::new($atomic::new(init)))
+            }
+
+            pub fn read(&self) -> AtomicReadGuard<$value> {
+                AtomicReadGuard(self.load())
+            }
+
+            pub fn load(&self) -> $value {
+                self.0.load(Ordering::SeqCst)
+            }
+
+            pub fn store(&self, value: $value) {
+                self.0.store(value, Ordering::SeqCst)
+            }
+
+            pub fn swap(&self, value: $value) -> $value {
+                self.0.swap(value, Ordering::SeqCst)
+            }
+
+            pub fn compare_exchange(&self, current: $value, new: $value) -> Result<$value, $value> {
+                self.0.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+            }
+
+            /// Applies `f` until it succeeds without interference, returning the previous value.
+            /// If `f` returns `None` the value is left unchanged and `Err` is returned.
+            pub fn fetch_update(
+                &self,
+                f: impl FnMut($value) -> Option<$value>,
+            ) -> Result<$value, $value> {
+                self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, f)
+            }
+
+            /// Replaces the value with `f(value)` and returns the new value. `f` is called again
+            /// with the fresh value whenever another thread changed it in between.
+            pub fn update(&self, mut f: impl FnMut($value) -> $value) -> $value {
+                let mut current = self.load();
+                loop {
+                    let new = f(current);
+                    match self.compare_exchange(current, new) {
+                        Ok(_) => return new,
+                        Err(actual) => current = actual,
+                    }
+                }
+            }
+
+            pub fn fetch_and(&self, value: $value) -> $value {
+                self.0.fetch_and(value, Ordering::SeqCst)
+            }
+
+            pub fn fetch_or(&self, value: $value) -> $value {
+                self.0.fetch_or(value, Ordering::SeqCst)
+            }
+
+            pub fn fetch_xor(&self, value: $value) -> $value {
+                self.0.fetch_xor(value, Ordering::SeqCst)
+            }
+        }
+
+        impl Clone for $name {
+            fn clone(&self) -> Self {
+                $name(self.0.clone())
+            }
+        }
+
+        impl From<$value> for $name {
+            fn from(value: $value) -> Self {
+                $name::new(value)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl crate::deps::serde::ser::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: crate::deps::serde::Serializer,
+            {
+                self.load().serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> crate::deps::serde::de::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: crate::deps::serde::Deserializer<'de>,
+            {
+                <$value>::deserialize(deserializer).map($name::new)
+            }
+        }
+    };
+}
+
+macro_rules! define_shared_atomic_int {
+    ($name:ident, $atomic:ident, $value:ty) => {
+        define_shared_atomic!($name, $atomic, $value);
+
+        impl $name {
+            pub fn fetch_add(&self, value: $value) -> $value {
+                self.0.fetch_add(value, Ordering::SeqCst)
+            }
+
+            pub fn fetch_sub(&self, value: $value) -> $value {
+                self.0.fetch_sub(value, Ordering::SeqCst)
+            }
+
+            pub fn fetch_max(&self, value: $value) -> $value {
+                self.0.fetch_max(value, Ordering::SeqCst)
+            }
+
+            pub fn fetch_min(&self, value: $value) -> $value {
+                self.0.fetch_min(value, Ordering::SeqCst)
+            }
+        }
+    };
+}
+
+define_shared_atomic!(SharedAtomicBool, AtomicBool, bool);
+define_shared_atomic_int!(SharedAtomicU8, AtomicU8, u8);
+define_shared_atomic_int!(SharedAtomicU16, AtomicU16, u16);
+define_shared_atomic_int!(SharedAtomicU32, AtomicU32, u32);
+define_shared_atomic_int!(SharedAtomicU64, AtomicU64, u64);
+define_shared_atomic_int!(SharedAtomicUsize, AtomicUsize, usize);
+define_shared_atomic_int!(SharedAtomicI8, AtomicI8, i8);
+define_shared_atomic_int!(SharedAtomicI16, AtomicI16, i16);
+define_shared_atomic_int!(SharedAtomicI32, AtomicI32, i32);
+define_shared_atomic_int!(SharedAtomicI64, AtomicI64, i64);
+define_shared_atomic_int!(SharedAtomicIsize, AtomicIsize, isize);
+
+#[test]
+fn test_shared_atomic_matches_shared_ptr_surface() {
+    let counter = SharedAtomicU64::new(0);
+    assert_eq!(counter.update(|v| v + 5), 5);
+    assert_eq!(*counter.read(), 5);
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    counter.fetch_add(1);
+                    counter.update(|v| v + 1);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(counter.load(), 8005);
+
+    let flag = SharedAtomicBool::default();
+    assert_eq!(flag.compare_exchange(false, true), Ok(false));
+    assert!(*flag.read());
+}
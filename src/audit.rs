@@ -0,0 +1,71 @@
+//! Bounded in-memory log of writes to opted-in pointers.
+use std::collections::VecDeque;
+use std::panic::Location;
+use std::time::SystemTime;
+
+use crate::deps::parking_lot::Mutex;
+
+/// One write to an audited pointer.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// When the write guard was acquired.
+    pub timestamp: SystemTime,
+    /// Where `write()` was called.
+    pub location: &'static Location<'static>,
+    /// The value as rendered by the pointer's describe function before and after the write,
+    /// if one was supplied.
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// A write that has started but whose guard has not been released yet.
+pub(crate) struct PendingAudit {
+    timestamp: SystemTime,
+    location: &'static Location<'static>,
+    before: Option<String>,
+}
+
+pub(crate) struct AuditLog<T: ?Sized> {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    capacity: usize,
+    describe: Option<fn(&T) -> String>,
+}
+
+impl<T: ?Sized> AuditLog<T> {
+    pub(crate) fn new(capacity: usize, describe: Option<fn(&T) -> String>) -> Self {
+        AuditLog {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            describe,
+        }
+    }
+
+    pub(crate) fn begin(&self, location: &'static Location<'static>, value: &T) -> PendingAudit {
+        PendingAudit {
+            timestamp: SystemTime::now(),
+            location,
+            before: self.describe.map(|describe| describe(value)),
+        }
+    }
+
+    pub(crate) fn finish(&self, pending: PendingAudit, value: &T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = AuditEntry {
+            timestamp: pending.timestamp,
+            location: pending.location,
+            before: pending.before,
+            after: self.describe.map(|describe| describe(value)),
+        };
+        let mut entries = self.entries.lock();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub(crate) fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().iter().cloned().collect()
+    }
+}
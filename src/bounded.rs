@@ -0,0 +1,160 @@
+//! Read access limited to a fixed number of concurrent readers.
+//!
+//! Some values hand out a scarce resource on their read path, such as a memory-mapped region
+//! with a limited number of descriptors. `BoundedSharedPtr` wraps an `arc_rwlock::SharedPtr` with
+//! a semaphore so that at most `max_readers` read guards exist at once. Further readers wait for
+//! a permit before they queue for the lock. Writers don't take a permit.
+use std::sync::Arc;
+
+use crate::arc_rwlock::{
+    ReadGuard,
+    SharedPtr,
+    WriteGuard,
+};
+use crate::deps::parking_lot::{
+    Condvar,
+    Mutex,
+};
+
+struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock();
+        while *permits == 0 {
+            self.released.wait(&mut permits);
+        }
+        *permits -= 1;
+        Permit(self)
+    }
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// A pointer that admits at most a fixed number of readers at a time. Clones share the value
+/// and the limit.
+pub struct BoundedSharedPtr<T> {
+    ptr: SharedPtr<T>,
+    readers: Arc<Semaphore>,
+}
+
+impl<T> BoundedSharedPtr<T> {
+    pub fn new(init: T, max_readers: usize) -> Self {
+        BoundedSharedPtr::from_shared(SharedPtr::new(init), max_readers)
+    }
+
+    /// Limits the readers of an existing pointer. Reads through other handles to it are not
+    /// counted.
+    pub fn from_shared(ptr: SharedPtr<T>, max_readers: usize) -> Self {
+        assert!(max_readers > 0, "a BoundedSharedPtr needs at least one reader permit");
+        BoundedSharedPtr {
+            ptr,
+            readers: Arc::new(Semaphore {
+                permits: Mutex::new(max_readers),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Waits for a reader permit, then for the read lock.
+    pub fn read(&self) -> BoundedReadGuard<'_, T> {
+        let permit = self.readers.acquire();
+        BoundedReadGuard {
+            guard: self.ptr.read(),
+            _permit: permit,
+        }
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        self.ptr.write()
+    }
+}
+
+impl<T> Clone for BoundedSharedPtr<T> {
+    fn clone(&self) -> Self {
+        BoundedSharedPtr {
+            ptr: self.ptr.clone(),
+            readers: self.readers.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for BoundedSharedPtr<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BoundedSharedPtr")
+            .field("value", &self.ptr)
+            .field("available_readers", &*self.readers.permits.lock())
+            .finish()
+    }
+}
+
+/// Shared access to the value, holding one of the reader permits.
+pub struct BoundedReadGuard<'a, T> {
+    // Declared first so the lock is released before the permit is returned.
+    guard: ReadGuard<'a, T>,
+    _permit: Permit<'a>,
+}
+
+impl<T> std::ops::Deref for BoundedReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> std::fmt::Debug for BoundedReadGuard<'_, T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.guard, f)
+    }
+}
+
+#[test]
+fn test_bounded_readers() {
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+    use std::time::Duration;
+
+    let ptr = BoundedSharedPtr::new(0u32, 2);
+    let active = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let readers: Vec<_> = (0..6)
+        .map(|_| {
+            let (ptr, active, peak) = (ptr.clone(), active.clone(), peak.clone());
+            std::thread::spawn(move || {
+                let guard = ptr.read();
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(10));
+                active.fetch_sub(1, Ordering::SeqCst);
+                *guard
+            })
+        })
+        .collect();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+
+    *ptr.write() += 1;
+    assert_eq!(*ptr.read(), 1);
+}
@@ -0,0 +1,232 @@
+//! A reader-biased `arc_rwlock` pointer for values read by many threads at once.
+//!
+//! Every `RwLock` read acquisition writes to the lock's reader count, so with hundreds of reader
+//! threads that one cache line bounces between cores even when nobody writes. `SharedBiased`
+//! follows BRAVO (Dice and Kogan, "BRAVO: Biased Locking for Reader-Writer Locks", 2019): while
+//! the pointer is reader-biased, a reader only publishes itself in one slot of a process-wide
+//! table, chosen by hashing the thread and the pointer, and reads the value without touching the
+//! lock. A writer takes the write lock, revokes the bias and waits until no slot names the
+//! pointer, which makes writes considerably slower. The bias comes back on a later read once a
+//! multiple of the time the last revocation took has passed, so write-heavy phases fall back to
+//! the plain lock.
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    OnceLock,
+};
+use std::time::Instant;
+
+use crate::arc_rwlock::{
+    ReadGuard,
+    SharedPtr,
+    WriteGuard,
+};
+use crate::by_address::Address;
+
+const SLOTS: usize = 4096;
+
+/// How many times the last revocation's duration the bias stays off for.
+const INHIBIT_MULTIPLIER: u32 = 9;
+
+static READERS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+
+thread_local! {
+    // Only its address is used, to tell threads apart.
+    static THREAD: u8 = const { 0 };
+}
+
+fn slot(address: usize) -> &'static AtomicUsize {
+    let thread = THREAD.with(|thread| (thread as *const u8).addr());
+    let hash = (thread ^ address.rotate_left(17)).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+    &READERS[(hash >> 7) % SLOTS]
+}
+
+fn nanos_since_start() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let elapsed = START.get_or_init(Instant::now).elapsed();
+    elapsed.as_nanos().min(u64::MAX as u128) as u64
+}
+
+struct Bias {
+    enabled: AtomicBool,
+    // In `nanos_since_start`; the bias is not re-enabled before then.
+    inhibit_until: AtomicU64,
+}
+
+/// An `arc_rwlock::SharedPtr` with reader-biased locking. Clones share the value.
+pub struct SharedBiased<T> {
+    value: SharedPtr<T>,
+    bias: Arc<Bias>,
+}
+
+impl<T> SharedBiased<T> {
+    pub fn new(init: T) -> Self {
+        let bias = Bias {
+            enabled: AtomicBool::new(true),
+            inhibit_until: AtomicU64::new(0),
+        };
+        SharedBiased {
+            value: SharedPtr::new(init),
+            bias: Arc::new(bias),
+        }
+    }
+
+    /// Like `RwLock::read`, this can deadlock if the thread already holds a read guard of the
+    /// same pointer while a writer is waiting.
+    pub fn read(&self) -> BiasedReadGuard<'_, T> {
+        if self.bias.enabled.load(Ordering::Relaxed) {
+            let address = self.value.address().addr();
+            let slot = slot(address);
+            let published = slot
+                .compare_exchange(0, address, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            if published {
+                if self.bias.enabled.load(Ordering::SeqCst) {
+                    // SAFETY: writers revoke the bias and then wait for this slot to be cleared
+                    // before touching the value, and the bias was still enabled after the slot
+                    // was published, so no writer can run until the guard is dropped.
+                    let value = unsafe { &*self.value.data_ptr() };
+                    return BiasedReadGuard(Held::Biased { slot, value });
+                }
+                slot.store(0, Ordering::Release);
+            }
+        }
+
+        let guard = self.value.read();
+        let now = nanos_since_start();
+        if !self.bias.enabled.load(Ordering::Relaxed)
+            && now >= self.bias.inhibit_until.load(Ordering::Relaxed)
+        {
+            // No writer can be revoking the bias while this read lock is held.
+            self.bias.enabled.store(true, Ordering::Release);
+        }
+        BiasedReadGuard(Held::Locked(guard))
+    }
+
+    /// Write-locks the value, first waiting for biased readers to finish if the pointer is
+    /// currently reader-biased.
+    #[track_caller]
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let guard = self.value.write();
+        if self.bias.enabled.load(Ordering::Relaxed) {
+            self.bias.enabled.store(false, Ordering::SeqCst);
+            let start = Instant::now();
+            let address = self.value.address().addr();
+            for reader in READERS.iter() {
+                while reader.load(Ordering::SeqCst) == address {
+                    std::thread::yield_now();
+                }
+            }
+            let inhibit = start.elapsed() * INHIBIT_MULTIPLIER;
+            let until = nanos_since_start().saturating_add(inhibit.as_nanos() as u64);
+            self.bias.inhibit_until.store(until, Ordering::Relaxed);
+        }
+        guard
+    }
+
+    /// Whether reads currently bypass the lock.
+    pub fn is_biased(&self) -> bool {
+        self.bias.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for SharedBiased<T> {
+    fn clone(&self) -> Self {
+        SharedBiased {
+            value: self.value.clone(),
+            bias: self.bias.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for SharedBiased<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedBiased")
+            .field("value", &&*self.read())
+            .field("biased", &self.is_biased())
+            .finish()
+    }
+}
+
+enum Held<'a, T> {
+    Biased {
+        slot: &'static AtomicUsize,
+        value: &'a T,
+    },
+    Locked(ReadGuard<'a, T>),
+}
+
+/// Shared access to the value of a `SharedBiased`, through either the bias or the read lock.
+pub struct BiasedReadGuard<'a, T>(Held<'a, T>);
+
+impl<T> std::ops::Deref for BiasedReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.0 {
+            Held::Biased { value, .. } => value,
+            Held::Locked(guard) => guard,
+        }
+    }
+}
+
+impl<T> Drop for BiasedReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Held::Biased { slot, .. } = self.0 {
+            slot.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for BiasedReadGuard<'_, T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_biased_reads_exclude_writers() {
+    let pair = SharedBiased::new((0u64, 0u64));
+    assert!(pair.is_biased());
+    assert!(matches!(pair.read().0, Held::Biased { .. }));
+
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let pair = pair.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2000 {
+                    let (first, second) = *pair.read();
+                    assert_eq!(first, second);
+                }
+            })
+        })
+        .collect();
+    for _ in 0..200 {
+        let mut guard = pair.write();
+        guard.0 += 1;
+        std::thread::yield_now();
+        guard.1 += 1;
+    }
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert_eq!(*pair.read(), (200, 200));
+
+    drop(pair.write());
+    assert!(!pair.is_biased());
+    while !pair.is_biased() {
+        assert!(matches!(pair.read().0, Held::Locked(_)));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
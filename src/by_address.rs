@@ -0,0 +1,131 @@
+//! Identity comparison for pointers.
+
+/// Pointers that can be compared by the allocation they point to.
+pub trait Address {
+    /// The address of the shared allocation. Equal for all clones of a pointer.
+    fn address(&self) -> *const ();
+}
+
+/// Wraps a pointer so that `Eq`, `Ord` and `Hash` compare which allocation it points to instead
+/// of locking and comparing values. Safe to use as a map key: comparisons never block or
+/// deadlock, and mutating the value through the pointer cannot change its position in the map.
+///
+/// Clippy's `mutable_key_type` lint cannot tell that the key ignores the value, so sets and maps
+/// of `ByAddress` keys need it allowed.
+#[derive(Debug, Clone, Default)]
+pub struct ByAddress<P>(pub P);
+
+impl<P> ByAddress<P> {
+    pub fn into_inner(self) -> P {
+        self.0
+    }
+}
+
+impl<P> std::ops::Deref for ByAddress<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+
+impl<P: Address> PartialEq for ByAddress<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.address() == other.0.address()
+    }
+}
+
+impl<P: Address> Eq for ByAddress<P> {}
+
+impl<P: Address> PartialOrd for ByAddress<P> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: Address> Ord for ByAddress<P> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.address().cmp(&other.0.address())
+    }
+}
+
+impl<P: Address> std::hash::Hash for ByAddress<P> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.address().hash(state)
+    }
+}
+
+impl<P> From<P> for ByAddress<P> {
+    fn from(ptr: P) -> Self {
+        ByAddress(ptr)
+    }
+}
+
+/// An opaque token identifying one shared allocation, returned by `SharedPtr::id`.
+///
+/// Equal for all clones of a pointer, and never reused: an allocation made at the address of one
+/// that has since been freed gets a new generation. Holding an id doesn't keep the value alive,
+/// and taking one never locks it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SharedPtrId {
+    address: usize,
+    generation: u64,
+}
+
+impl SharedPtrId {
+    pub(crate) fn new(address: *const (), generation: u64) -> Self {
+        SharedPtrId {
+            address: address.addr(),
+            generation,
+        }
+    }
+}
+
+/// Formats as `address#generation`, e.g. `0x55d0c2a4e2b0#17`.
+impl std::fmt::Display for SharedPtrId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:#x}#{}", self.address, self.generation)
+    }
+}
+
+/// Hands out the generation of a new allocation.
+pub(crate) fn next_generation() -> u64 {
+    static GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    GENERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+#[allow(clippy::mutable_key_type)]
+fn test_by_address() {
+    use std::collections::HashSet;
+
+    use crate::arc_mutex::SharedPtr;
+
+    let first = SharedPtr::new(0u32);
+    let second = SharedPtr::new(0u32);
+    let mut set = HashSet::new();
+    assert!(set.insert(ByAddress(first.clone())));
+    assert!(set.insert(ByAddress(second)));
+    assert!(!set.insert(ByAddress(first.clone())));
+
+    // Holding the lock doesn't block lookups, and writes don't move the key.
+    let mut guard = first.write();
+    *guard += 1;
+    assert!(set.contains(&ByAddress(first.clone())));
+}
+
+#[test]
+#[cfg(feature = "rc")]
+fn test_shared_ptr_id() {
+    use crate::rc_refcell::SharedPtr;
+
+    let first = SharedPtr::new(0u32);
+    let id = first.id();
+    assert_eq!(id, first.clone().id());
+    assert_ne!(id, SharedPtr::new(0u32).id());
+
+    let _guard = first.write();
+    assert_eq!(first.id(), id);
+    assert!(id.to_string().contains('#'));
+}
@@ -0,0 +1,101 @@
+//! Collections of pointers.
+
+/// Strong pointers with a weak counterpart, implemented by every backend's `SharedPtr`.
+pub trait Downgrade: Sized {
+    type Weak;
+
+    fn downgrade(&self) -> Self::Weak;
+
+    fn upgrade(weak: &Self::Weak) -> Option<Self>;
+}
+
+/// A list of weak pointers that drops its dead entries as it is iterated, for observer lists and
+/// other broadcast targets that shouldn't keep their members alive.
+pub struct WeakList<P: Downgrade> {
+    entries: Vec<P::Weak>,
+}
+
+impl<P: Downgrade> WeakList<P> {
+    pub fn new() -> Self {
+        WeakList {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, ptr: &P) {
+        self.entries.push(ptr.downgrade());
+    }
+
+    /// Calls `f` with every entry that is still alive, in the order they were pushed, and removes
+    /// the dead ones.
+    pub fn for_each_alive(&mut self, mut f: impl FnMut(&P)) {
+        self.entries.retain(|weak| match P::upgrade(weak) {
+            Some(ptr) => {
+                f(&ptr);
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Removes the entries whose value has been dropped.
+    pub fn prune(&mut self) {
+        self.entries.retain(|weak| P::upgrade(weak).is_some());
+    }
+
+    /// The number of entries, including dead ones not yet pruned.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<P: Downgrade> Default for WeakList<P> {
+    fn default() -> Self {
+        WeakList::new()
+    }
+}
+
+impl<P> Clone for WeakList<P>
+where
+    P: Downgrade,
+    P::Weak: Clone,
+{
+    fn clone(&self) -> Self {
+        WeakList {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<P: Downgrade> std::fmt::Debug for WeakList<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WeakList").field("len", &self.entries.len()).finish()
+    }
+}
+
+#[test]
+#[cfg(feature = "rc")]
+fn test_weak_list_prunes_dead_entries() {
+    use crate::rc_refcell::SharedPtr;
+
+    let first = SharedPtr::new(1u32);
+    let second = SharedPtr::new(2u32);
+    let mut list = WeakList::new();
+    list.push(&first);
+    list.push(&second);
+    list.push(&SharedPtr::new(3u32));
+    assert_eq!(list.len(), 3);
+
+    let mut seen = Vec::new();
+    list.for_each_alive(|ptr| seen.push(*ptr.read()));
+    assert_eq!(seen, [1, 2]);
+    assert_eq!(list.len(), 2);
+
+    drop(first);
+    list.prune();
+    assert_eq!(list.len(), 1);
+}
@@ -0,0 +1,55 @@
+//! Equality between pointers of different backends, so a single threaded reference model can be
+//! checked against the threaded implementation with `assert_eq!`.
+//!
+//! As with pointers of the same backend, both values are locked in address order so that two
+//! threads comparing the same pair in opposite directions can't deadlock.
+use crate::by_address::Address;
+
+macro_rules! impl_cross_backend_eq {
+    ($($left:ident == $right:ident),*) => {
+        $(
+            impl<T> PartialEq<crate::$right::SharedPtr<T>> for crate::$left::SharedPtr<T>
+            where
+                T: PartialEq,
+            {
+                fn eq(&self, other: &crate::$right::SharedPtr<T>) -> bool {
+                    if self.address() < other.address() {
+                        let this = self.read();
+                        *this == *other.read()
+                    } else {
+                        let that = other.read();
+                        *self.read() == *that
+                    }
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(all(feature = "rc", feature = "mutex"))]
+impl_cross_backend_eq!(rc_refcell == arc_mutex, arc_mutex == rc_refcell);
+#[cfg(all(feature = "rc", feature = "rwlock"))]
+impl_cross_backend_eq!(rc_refcell == arc_rwlock, arc_rwlock == rc_refcell);
+#[cfg(all(feature = "mutex", feature = "rwlock"))]
+impl_cross_backend_eq!(arc_mutex == arc_rwlock, arc_rwlock == arc_mutex);
+
+#[test]
+#[cfg(all(feature = "rc", feature = "mutex", feature = "rwlock"))]
+fn test_cross_backend_eq() {
+    use crate::{
+        arc_mutex,
+        arc_rwlock,
+        rc_refcell,
+    };
+
+    let model = rc_refcell::SharedPtr::new(vec![1u32, 2]);
+    let threaded = arc_rwlock::SharedPtr::new(vec![1u32, 2]);
+    let locked = arc_mutex::SharedPtr::new(vec![1u32, 2]);
+    assert_eq!(model, threaded);
+    assert_eq!(threaded, model);
+    assert_eq!(locked, threaded);
+
+    threaded.write().push(3);
+    assert_ne!(model, threaded);
+    assert_ne!(threaded, locked);
+}
@@ -0,0 +1,159 @@
+//! Conversions between the backends, so state built single-threaded can be promoted for
+//! multi-threaded use and back.
+//!
+//! The value moves if the pointer is unique and is cloned otherwise, leaving the other pointers
+//! on the old backend. The `try_` variants only move, so they work for values that can't be
+//! cloned.
+//!
+//! A moved value takes its `on_last_drop` finalizers along, and they run when the new pointer's
+//! value is dropped. `rc_refcell` finalizers need not be `Send`, so a unique `rc_refcell` pointer
+//! that has any can't be moved to `arc_rwlock` at all. Apart from that only the value carries
+//! over: tracking, statistics, audit logs, invariants and observers stay with the old
+//! allocation.
+#[cfg(feature = "mutex")]
+use crate::arc_mutex;
+#[cfg(feature = "rwlock")]
+use crate::arc_rwlock;
+use crate::rc_refcell;
+
+#[cfg(feature = "rwlock")]
+impl<T> rc_refcell::SharedPtr<T> {
+    /// Moves the value to an `arc_rwlock` pointer if this is the only pointer to it and it has
+    /// no finalizers, and returns the pointer unchanged otherwise.
+    pub fn try_into_threadsafe(self) -> Result<arc_rwlock::SharedPtr<T>, Self> {
+        if self.finalizes_on_drop() {
+            return Err(self);
+        }
+        self.try_unwrap().map(arc_rwlock::SharedPtr::new)
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: Clone> rc_refcell::SharedPtr<T> {
+    /// # Panics
+    ///
+    /// If this is the only pointer to the value and it has `on_last_drop` finalizers: they can't
+    /// move to another thread, and cloning would run them while the value lives on. Use
+    /// `try_into_threadsafe` to get the pointer back instead.
+    pub fn into_threadsafe(self) -> arc_rwlock::SharedPtr<T> {
+        match self.try_into_threadsafe() {
+            Ok(threadsafe) => threadsafe,
+            Err(shared) => {
+                assert!(
+                    !shared.finalizes_on_drop(),
+                    "cannot move on_last_drop finalizers of an rc_refcell pointer to another thread"
+                );
+                arc_rwlock::SharedPtr::new(T::clone(&shared.read()))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T> arc_rwlock::SharedPtr<T> {
+    /// Moves the value to an `rc_refcell` pointer if this is the only pointer to it, and returns
+    /// the pointer unchanged otherwise.
+    pub fn try_into_local(self) -> Result<rc_refcell::SharedPtr<T>, Self> {
+        let (value, finalizers) = self.try_unwrap_parts()?;
+        let local = rc_refcell::SharedPtr::new(value);
+        for finalizer in finalizers.into_vec() {
+            local.on_last_drop(move || finalizer.finalize());
+        }
+        Ok(local)
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: Clone> arc_rwlock::SharedPtr<T> {
+    pub fn into_local(self) -> rc_refcell::SharedPtr<T> {
+        self.try_into_local()
+            .unwrap_or_else(|shared| rc_refcell::SharedPtr::new(T::clone(&shared.read())))
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T> arc_mutex::SharedPtr<T> {
+    /// Moves the value to an `rc_refcell` pointer if this is the only pointer to it, and returns
+    /// the pointer unchanged otherwise.
+    pub fn try_into_local(self) -> Result<rc_refcell::SharedPtr<T>, Self> {
+        let (value, finalizers) = self.try_unwrap_parts()?;
+        let local = rc_refcell::SharedPtr::new(value);
+        for finalizer in finalizers.into_vec() {
+            local.on_last_drop(move || finalizer.finalize());
+        }
+        Ok(local)
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: Clone> arc_mutex::SharedPtr<T> {
+    pub fn into_local(self) -> rc_refcell::SharedPtr<T> {
+        self.try_into_local()
+            .unwrap_or_else(|shared| rc_refcell::SharedPtr::new(T::clone(&shared.read())))
+    }
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_backend_conversion() {
+    let local = rc_refcell::SharedPtr::new(vec![1u32]);
+    let threadsafe = local.into_threadsafe();
+    let worker = {
+        let threadsafe = threadsafe.clone();
+        std::thread::spawn(move || threadsafe.write().push(2))
+    };
+    worker.join().unwrap();
+
+    let kept = threadsafe.clone();
+    let local = threadsafe.into_local();
+    local.write().push(3);
+    assert_eq!(*local.read(), [1, 2, 3]);
+    assert_eq!(*kept.read(), [1, 2]);
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_try_backend_conversion() {
+    // Not `Clone`.
+    #[derive(Debug)]
+    struct Socket(u32);
+
+    let local = rc_refcell::SharedPtr::new(Socket(1));
+    let kept = local.clone();
+    let local = local.try_into_threadsafe().unwrap_err();
+    drop(kept);
+    let threadsafe = local.try_into_threadsafe().unwrap();
+    let local = threadsafe.try_into_local().unwrap();
+    assert_eq!(local.read().0, 1);
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_conversion_keeps_finalizers() {
+    use std::sync::atomic::{
+        AtomicU32,
+        Ordering,
+    };
+    use std::sync::Arc;
+
+    let finalized = Arc::new(AtomicU32::new(0));
+    let threadsafe = arc_rwlock::SharedPtr::new(1u32);
+    threadsafe.on_last_drop({
+        let finalized = finalized.clone();
+        move || {
+            finalized.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    let local = threadsafe.try_into_local().unwrap();
+    assert_eq!(finalized.load(Ordering::SeqCst), 0);
+
+    // Moving it back would send the finalizer to other threads.
+    let local = local.try_into_threadsafe().unwrap_err();
+    let alias = local.clone();
+    let threadsafe = local.into_threadsafe();
+    assert_eq!(finalized.load(Ordering::SeqCst), 0);
+    let local = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alias.into_threadsafe()));
+    assert!(local.is_err());
+    assert_eq!(finalized.load(Ordering::SeqCst), 1);
+    assert_eq!(*threadsafe.read(), 1);
+}
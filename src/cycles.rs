@@ -0,0 +1,392 @@
+//! Leak detection for reference cycles between `rc_refcell` pointers.
+//!
+//! `Rc` frees a value once nothing points to it, but two values that point to each other keep
+//! each other alive forever. To find such cycles, implement `Trace` for the values that hold
+//! pointers, `register` the pointers to watch, and call `leaks()`, typically at the end of a test
+//! or before shutdown:
+//!
+//! ```ignore
+//! impl Trace for Node {
+//!     fn trace(&self, tracer: &mut Tracer) {
+//!         for child in &self.children {
+//!             tracer.edge(child);
+//!         }
+//!     }
+//! }
+//!
+//! cycles::register(&root);
+//! assert!(cycles::leaks().is_empty(), "{:#?}", cycles::leaks());
+//! ```
+//!
+//! Starting from the registered pointers, `leaks()` follows every traced edge and compares each
+//! pointer's strong count with the number of traced edges into it. The difference is held from
+//! outside the graph, which keeps the pointer and everything it reaches alive. Whatever is left
+//! can never be freed, and is reported grouped into strongly connected components.
+//!
+//! An edge that `trace` forgets only makes the detector more conservative, but reporting an edge
+//! that is not a strong reference (e.g. an upgraded `WeakPtr`) can cause false reports. Values
+//! that are borrowed mutably while `leaks()` runs can't be traced and count as alive.
+//!
+//! Registration is only recorded in debug builds, so release builds report no leaks and pay
+//! nothing for it.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::by_address::Address;
+use crate::rc_refcell::{
+    SharedPtr,
+    WeakPtr,
+};
+
+/// Values that report the pointers they hold, see the module docs.
+pub trait Trace {
+    /// Calls `tracer.edge` with every `rc_refcell::SharedPtr` held by this value.
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// Collects the edges reported by `Trace::trace`.
+#[derive(Default)]
+pub struct Tracer {
+    edges: Vec<Box<dyn Traced>>,
+}
+
+impl Tracer {
+    pub fn edge<T: Trace + 'static>(&mut self, ptr: &SharedPtr<T>) {
+        self.edges.push(Box::new(WeakPtr::downgrade(ptr)));
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Tracer").field("edges", &self.edges.len()).finish()
+    }
+}
+
+/// A type-erased pointer that can be traced without keeping its value alive.
+trait Traced {
+    fn address(&self) -> *const ();
+
+    fn strong_count(&self) -> usize;
+
+    /// Returns `None` if the value is gone or mutably borrowed.
+    fn edges(&self) -> Option<Vec<Box<dyn Traced>>>;
+
+    fn label(&self) -> String;
+
+    fn duplicate(&self) -> Box<dyn Traced>;
+}
+
+impl<T: Trace + 'static> Traced for WeakPtr<T> {
+    fn address(&self) -> *const () {
+        Address::address(self)
+    }
+
+    fn strong_count(&self) -> usize {
+        WeakPtr::strong_count(self)
+    }
+
+    fn edges(&self) -> Option<Vec<Box<dyn Traced>>> {
+        let ptr = self.upgrade()?;
+        let value = ptr.try_read()?;
+        let mut tracer = Tracer::default();
+        value.trace(&mut tracer);
+        Some(tracer.edges)
+    }
+
+    fn label(&self) -> String {
+        let name = self.upgrade().and_then(|ptr| ptr.name().map(str::to_owned));
+        name.unwrap_or_else(|| std::any::type_name::<T>().to_owned())
+    }
+
+    fn duplicate(&self) -> Box<dyn Traced> {
+        Box::new(self.clone())
+    }
+}
+
+thread_local! {
+    static REGISTERED: RefCell<Vec<Box<dyn Traced>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Adds `ptr` to the pointers `leaks()` starts from. Pointers reachable from it through traced
+/// edges don't need to be registered. Does nothing in release builds.
+pub fn register<T: Trace + 'static>(ptr: &SharedPtr<T>) {
+    if cfg!(debug_assertions) {
+        let node = Box::new(WeakPtr::downgrade(ptr));
+        REGISTERED.with(|registered| registered.borrow_mut().push(node));
+    }
+}
+
+/// A pointer that can never be freed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaked {
+    pub address: *const (),
+    /// The pointer's name if it has one, otherwise the type of its value.
+    pub label: String,
+    pub strong_count: usize,
+}
+
+/// A strongly connected component of leaked pointers: every member reaches every other, and
+/// nothing outside the graph reaches any of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    pub members: Vec<Leaked>,
+}
+
+struct Graph {
+    nodes: Vec<Box<dyn Traced>>,
+    edges: Vec<Vec<usize>>,
+    /// Nodes whose value couldn't be borrowed, so their edges are unknown.
+    untraced: Vec<bool>,
+    index: HashMap<*const (), usize>,
+}
+
+impl Graph {
+    /// Follows every edge reachable from `roots`.
+    fn explore(roots: impl IntoIterator<Item = Box<dyn Traced>>) -> Graph {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            untraced: Vec::new(),
+            index: HashMap::new(),
+        };
+        let mut pending = Vec::new();
+        for root in roots {
+            graph.insert(root, &mut pending);
+        }
+        while let Some(node) = pending.pop() {
+            match graph.nodes[node].edges() {
+                Some(edges) => {
+                    for edge in edges {
+                        let target = graph.insert(edge, &mut pending);
+                        graph.edges[node].push(target);
+                    }
+                }
+                None => graph.untraced[node] = true,
+            }
+        }
+        graph
+    }
+
+    fn insert(&mut self, node: Box<dyn Traced>, pending: &mut Vec<usize>) -> usize {
+        if let Some(&index) = self.index.get(&node.address()) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.index.insert(node.address(), index);
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        self.untraced.push(false);
+        pending.push(index);
+        index
+    }
+
+    /// Nodes that are kept alive from outside the graph, directly or transitively.
+    fn alive(&self) -> Vec<bool> {
+        let mut incoming = vec![0; self.nodes.len()];
+        for targets in &self.edges {
+            for &target in targets {
+                incoming[target] += 1;
+            }
+        }
+        let mut alive = vec![false; self.nodes.len()];
+        let mut pending: Vec<usize> = (0..self.nodes.len())
+            .filter(|&node| self.untraced[node] || self.nodes[node].strong_count() > incoming[node])
+            .collect();
+        while let Some(node) = pending.pop() {
+            if !std::mem::replace(&mut alive[node], true) {
+                pending.extend(&self.edges[node]);
+            }
+        }
+        alive
+    }
+
+    /// Tarjan's algorithm over the nodes for which `include` holds. The depth-first search keeps
+    /// its own stack, since leaked lists can be far deeper than the thread's.
+    fn components(&self, include: &[bool]) -> Vec<Vec<usize>> {
+        struct State {
+            index: Vec<Option<usize>>,
+            low: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            next: usize,
+            components: Vec<Vec<usize>>,
+        }
+
+        impl State {
+            fn visit(&mut self, node: usize) {
+                self.index[node] = Some(self.next);
+                self.low[node] = self.next;
+                self.next += 1;
+                self.stack.push(node);
+                self.on_stack[node] = true;
+            }
+
+            /// Called once every edge of `node` has been followed.
+            fn finish(&mut self, node: usize) {
+                if Some(self.low[node]) == self.index[node] {
+                    let mut component = Vec::new();
+                    loop {
+                        let member = self.stack.pop().unwrap();
+                        self.on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let count = self.nodes.len();
+        let mut state = State {
+            index: vec![None; count],
+            low: vec![0; count],
+            on_stack: vec![false; count],
+            stack: Vec::new(),
+            next: 0,
+            components: Vec::new(),
+        };
+        // The current search path, each node with the position of the next edge to follow.
+        let mut path: Vec<(usize, usize)> = Vec::new();
+        for root in 0..count {
+            if !include[root] || state.index[root].is_some() {
+                continue;
+            }
+            state.visit(root);
+            path.push((root, 0));
+            while let Some(&(node, edge)) = path.last() {
+                match self.edges[node].get(edge) {
+                    Some(&target) => {
+                        path.last_mut().unwrap().1 += 1;
+                        if !include[target] {
+                            continue;
+                        }
+                        match state.index[target] {
+                            None => {
+                                state.visit(target);
+                                path.push((target, 0));
+                            }
+                            Some(index) if state.on_stack[target] => {
+                                state.low[node] = state.low[node].min(index);
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                    None => {
+                        path.pop();
+                        if let Some(&(parent, _)) = path.last() {
+                            state.low[parent] = state.low[parent].min(state.low[node]);
+                        }
+                        state.finish(node);
+                    }
+                }
+            }
+        }
+        state.components
+    }
+}
+
+/// Returns every cycle of pointers reachable from the registered ones that can never be freed.
+/// Always empty in release builds.
+pub fn leaks() -> Vec<Cycle> {
+    let roots = REGISTERED.with(|registered| {
+        let mut registered = registered.borrow_mut();
+        registered.retain(|node| node.strong_count() > 0);
+        registered.iter().map(|node| node.duplicate()).collect::<Vec<_>>()
+    });
+    let graph = Graph::explore(roots);
+    let leaked: Vec<bool> = graph.alive().into_iter().map(|alive| !alive).collect();
+    graph
+        .components(&leaked)
+        .into_iter()
+        .filter(|component| {
+            component.len() > 1 || graph.edges[component[0]].contains(&component[0])
+        })
+        .map(|component| Cycle {
+            members: component
+                .into_iter()
+                .map(|node| Leaked {
+                    address: graph.nodes[node].address(),
+                    label: graph.nodes[node].label(),
+                    strong_count: graph.nodes[node].strong_count(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn test_leaks_report_unreachable_cycles() {
+    struct Node {
+        children: Vec<SharedPtr<Node>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            for child in &self.children {
+                tracer.edge(child);
+            }
+        }
+    }
+
+    let node = |children| SharedPtr::new(Node { children });
+
+    // a -> b -> a, held from outside through `a`.
+    let a = node(vec![]);
+    let b = node(vec![a.clone()]);
+    a.write().children.push(b.clone());
+    register(&a);
+    drop(b);
+    assert!(leaks().is_empty());
+
+    // Dropping the last outside reference leaks the cycle, and the acyclic tail hanging off it.
+    let tail = SharedPtr::new_named("tail", Node { children: vec![] });
+    a.write().children.push(tail.clone());
+    let loop_ = node(vec![]);
+    loop_.write().children.push(loop_.clone());
+    register(&loop_);
+    drop((a, tail, loop_));
+
+    let mut sizes: Vec<usize> = leaks().iter().map(|cycle| cycle.members.len()).collect();
+    sizes.sort();
+    assert_eq!(sizes, [1, 2]);
+    assert!(leaks().iter().all(|cycle| cycle.members.iter().all(|leaked| leaked.label != "tail")));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn test_leaks_in_deep_graphs() {
+    struct Node {
+        next: Option<SharedPtr<Node>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            if let Some(next) = &self.next {
+                tracer.edge(next);
+            }
+        }
+    }
+
+    // A ring far deeper than a recursive search could follow.
+    let nodes: Vec<_> = (0..100_000).map(|_| SharedPtr::new(Node { next: None })).collect();
+    for (node, next) in nodes.iter().zip(nodes.iter().cycle().skip(1)) {
+        node.write().next = Some(next.clone());
+    }
+    register(&nodes[0]);
+    assert!(leaks().is_empty());
+
+    let weak: Vec<_> = nodes.iter().map(WeakPtr::downgrade).collect();
+    drop(nodes);
+    let leaked = leaks();
+    assert_eq!(leaked.len(), 1);
+    assert_eq!(leaked[0].members.len(), 100_000);
+
+    // Break the ring without dropping it recursively.
+    let nodes: Vec<_> = weak.iter().map(|node| node.upgrade().unwrap()).collect();
+    for node in &nodes {
+        node.write().next = None;
+    }
+}
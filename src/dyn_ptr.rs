@@ -0,0 +1,179 @@
+//! A pointer whose backend is picked at runtime.
+//!
+//! `DynSharedPtr` holds either an `arc_mutex` or an `arc_rwlock` pointer and dispatches every
+//! call with a `match`, so the backend can come from a config flag and be compared in production
+//! without recompiling. The branch costs a few nanoseconds per access. `rc_refcell` is left out
+//! because holding it would make every `DynSharedPtr` `!Send`.
+use std::str::FromStr;
+
+use crate::{
+    arc_mutex,
+    arc_rwlock,
+};
+
+/// The backends a `DynSharedPtr` can be created with. Parses from `"mutex"` and `"rwlock"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Mutex,
+    RwLock,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mutex" => Ok(Backend::Mutex),
+            "rwlock" => Ok(Backend::RwLock),
+            other => Err(format!("unknown backend {:?}, expected \"mutex\" or \"rwlock\"", other)),
+        }
+    }
+}
+
+/// A `SharedPtr` of either thread-safe backend. Clones share the value.
+pub enum DynSharedPtr<T> {
+    Mutex(arc_mutex::SharedPtr<T>),
+    RwLock(arc_rwlock::SharedPtr<T>),
+}
+
+impl<T> DynSharedPtr<T> {
+    pub fn new(init: T, backend: Backend) -> Self {
+        match backend {
+            Backend::Mutex => DynSharedPtr::Mutex(arc_mutex::SharedPtr::new(init)),
+            Backend::RwLock => DynSharedPtr::RwLock(arc_rwlock::SharedPtr::new(init)),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            DynSharedPtr::Mutex(_) => Backend::Mutex,
+            DynSharedPtr::RwLock(_) => Backend::RwLock,
+        }
+    }
+
+    pub fn read(&self) -> DynReadGuard<'_, T> {
+        match self {
+            DynSharedPtr::Mutex(ptr) => DynReadGuard::Mutex(ptr.read()),
+            DynSharedPtr::RwLock(ptr) => DynReadGuard::RwLock(ptr.read()),
+        }
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> DynWriteGuard<'_, T> {
+        match self {
+            DynSharedPtr::Mutex(ptr) => DynWriteGuard::Mutex(ptr.write()),
+            DynSharedPtr::RwLock(ptr) => DynWriteGuard::RwLock(ptr.write()),
+        }
+    }
+}
+
+impl<T> Clone for DynSharedPtr<T> {
+    fn clone(&self) -> Self {
+        match self {
+            DynSharedPtr::Mutex(ptr) => DynSharedPtr::Mutex(ptr.clone()),
+            DynSharedPtr::RwLock(ptr) => DynSharedPtr::RwLock(ptr.clone()),
+        }
+    }
+}
+
+impl<T> From<arc_mutex::SharedPtr<T>> for DynSharedPtr<T> {
+    fn from(ptr: arc_mutex::SharedPtr<T>) -> Self {
+        DynSharedPtr::Mutex(ptr)
+    }
+}
+
+impl<T> From<arc_rwlock::SharedPtr<T>> for DynSharedPtr<T> {
+    fn from(ptr: arc_rwlock::SharedPtr<T>) -> Self {
+        DynSharedPtr::RwLock(ptr)
+    }
+}
+
+impl<T> std::fmt::Debug for DynSharedPtr<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DynSharedPtr::Mutex(ptr) => f.debug_tuple("Mutex").field(ptr).finish(),
+            DynSharedPtr::RwLock(ptr) => f.debug_tuple("RwLock").field(ptr).finish(),
+        }
+    }
+}
+
+/// Shared access to the value of a `DynSharedPtr`.
+pub enum DynReadGuard<'a, T> {
+    Mutex(arc_mutex::ReadGuard<'a, T>),
+    RwLock(arc_rwlock::ReadGuard<'a, T>),
+}
+
+impl<T> std::ops::Deref for DynReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            DynReadGuard::Mutex(guard) => guard,
+            DynReadGuard::RwLock(guard) => guard,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for DynReadGuard<'_, T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to the value of a `DynSharedPtr`.
+pub enum DynWriteGuard<'a, T> {
+    Mutex(arc_mutex::WriteGuard<'a, T>),
+    RwLock(arc_rwlock::WriteGuard<'a, T>),
+}
+
+impl<T> std::ops::Deref for DynWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            DynWriteGuard::Mutex(guard) => guard,
+            DynWriteGuard::RwLock(guard) => guard,
+        }
+    }
+}
+
+impl<T> std::ops::DerefMut for DynWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            DynWriteGuard::Mutex(guard) => guard,
+            DynWriteGuard::RwLock(guard) => guard,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for DynWriteGuard<'_, T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_dyn_shared_ptr() {
+    for flag in ["mutex", "rwlock"] {
+        let backend: Backend = flag.parse().unwrap();
+        let ptr = DynSharedPtr::new(vec![1u32], backend);
+        assert_eq!(ptr.backend(), backend);
+
+        let writer = {
+            let ptr = ptr.clone();
+            std::thread::spawn(move || ptr.write().push(2))
+        };
+        writer.join().unwrap();
+        assert_eq!(*ptr.read(), [1, 2]);
+    }
+    assert!("spinlock".parse::<Backend>().is_err());
+}
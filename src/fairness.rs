@@ -0,0 +1,194 @@
+//! Lock acquisition policies.
+
+/// How a pointer's lock arbitrates between waiting readers and writers, fixed when the pointer is
+/// created with `SharedPtr::new_with_fairness`.
+///
+/// `rc_refcell` never waits, so the policy has no effect there. `arc_mutex` makes no distinction
+/// between readers and writers, so only `Fair` changes its behaviour.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Fairness {
+    /// New readers queue behind a waiting writer, so a steady stream of readers can't starve
+    /// writers. This is parking_lot's own policy and the default.
+    #[default]
+    WriterPreferring,
+    /// Readers take the lock whenever no writer holds it, even if writers are waiting. Keeps read
+    /// latency low at the risk of starving writers under read-heavy load.
+    ReaderPreferring,
+    /// Releasing a write guard hands the lock directly to the longest waiting thread, so waiters
+    /// are served in the order they arrived. Costs throughput, since the releasing thread can no
+    /// longer immediately re-acquire the lock.
+    Fair,
+}
+
+/// Lock guards that can hand their lock directly to the longest waiting thread.
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+pub trait UnlockFair: std::ops::Deref + Sized {
+    fn unlock_fair(self);
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<T: ?Sized> UnlockFair for crate::deps::parking_lot::MutexGuard<'_, T> {
+    fn unlock_fair(self) {
+        crate::deps::parking_lot::MutexGuard::unlock_fair(self)
+    }
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<T: ?Sized> UnlockFair for crate::deps::parking_lot::RwLockReadGuard<'_, T> {
+    fn unlock_fair(self) {
+        crate::deps::parking_lot::RwLockReadGuard::unlock_fair(self)
+    }
+}
+
+/// Shared access to the value of an `arc_mutex` or `arc_rwlock` pointer. Under `Fair`, releasing
+/// it hands the lock to the longest waiting thread, as releasing a write guard does.
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+pub struct ReadGuard<G: UnlockFair> {
+    // Only `None` once the guard has been released in `drop`.
+    guard: Option<G>,
+    fairness: Fairness,
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<G: UnlockFair> ReadGuard<G> {
+    pub(crate) fn new(guard: G, fairness: Fairness) -> Self {
+        ReadGuard {
+            guard: Some(guard),
+            fairness,
+        }
+    }
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<G: UnlockFair> std::ops::Deref for ReadGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        self.guard.as_deref().unwrap()
+    }
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<G: UnlockFair> Drop for ReadGuard<G> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            match self.fairness {
+                Fairness::Fair => guard.unlock_fair(),
+                Fairness::WriterPreferring | Fairness::ReaderPreferring => drop(guard),
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+impl<G> std::fmt::Debug for ReadGuard<G>
+where
+    G: UnlockFair,
+    G::Target: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// SAFETY: derefs to wherever `G` does.
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+unsafe impl<G: UnlockFair + crate::field::StableGuard> crate::field::StableGuard for ReadGuard<G> {}
+
+/// Waits until the thread spawned with `name` is parked on a lock. The waiters in these tests
+/// have nothing else to block on once their acquisition has been counted as contended, so on
+/// Linux the thread being asleep in the kernel means it is queued on the lock.
+#[cfg(all(test, any(feature = "mutex", feature = "rwlock")))]
+fn wait_until_parked(name: &str, contended: impl Fn() -> bool) {
+    while !contended() {
+        std::thread::yield_now();
+    }
+    #[cfg(target_os = "linux")]
+    loop {
+        let asleep = std::fs::read_dir("/proc/self/task").unwrap().flatten().any(|task| {
+            let comm = std::fs::read_to_string(task.path().join("comm")).unwrap_or_default();
+            let stat = std::fs::read_to_string(task.path().join("stat")).unwrap_or_default();
+            // The state follows the parenthesized thread name.
+            let state = stat.rsplit(") ").next().and_then(|rest| rest.chars().next());
+            comm.trim_end() == name && state == Some('S')
+        });
+        if asleep {
+            return;
+        }
+        std::thread::yield_now();
+    }
+    // Elsewhere there is no way to observe a parked thread, so give the waiter time to park.
+    #[cfg(not(target_os = "linux"))]
+    std::thread::sleep(std::time::Duration::from_millis(20));
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_reader_preferring_reads_past_waiting_writer() {
+    use crate::arc_rwlock::SharedPtr;
+
+    let ptr = SharedPtr::builder(0u32).fairness(Fairness::ReaderPreferring).with_stats().build();
+    assert_eq!(ptr.fairness(), Fairness::ReaderPreferring);
+    assert_eq!(SharedPtr::new(0u32).fairness(), Fairness::WriterPreferring);
+
+    let held = ptr.read();
+    let writer = {
+        let ptr = ptr.clone();
+        let writer = std::thread::Builder::new().name("prefer-writer".into());
+        writer.spawn(move || *ptr.write() += 1).unwrap()
+    };
+    wait_until_parked("prefer-writer", || ptr.stats().unwrap().contended_writes == 1);
+    let reader = {
+        let ptr = ptr.clone();
+        std::thread::spawn(move || *ptr.read())
+    };
+    assert_eq!(reader.join().unwrap(), 0);
+    drop(held);
+    writer.join().unwrap();
+    assert_eq!(*ptr.read(), 1);
+}
+
+/// Queues four writers, in order, behind a read guard of a `Fair` pointer and releases it.
+#[cfg(all(test, any(feature = "mutex", feature = "rwlock")))]
+macro_rules! test_fair_release {
+    ($backend:ident, $name:literal) => {{
+        use crate::$backend::SharedPtr;
+
+        let ptr = SharedPtr::builder(Vec::new()).fairness(Fairness::Fair).with_stats().build();
+        let read = ptr.read();
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let waiter = ptr.clone();
+                let name = format!("{}-{}", $name, i);
+                let thread = std::thread::Builder::new().name(name.clone());
+                let thread = thread.spawn(move || waiter.write().push(i)).unwrap();
+                wait_until_parked(&name, || ptr.stats().unwrap().contended_writes == i + 1);
+                thread
+            })
+            .collect();
+        // Releasing hands the lock straight to the first waiter and each waiter to the next, so
+        // the lock is not free again until all of them have written, and writing right away has
+        // to queue behind them.
+        drop(read);
+        if let Some(read) = ptr.try_read() {
+            assert_eq!(*read, [0, 1, 2, 3]);
+        }
+        ptr.write().push(4);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*ptr.read(), [0, 1, 2, 3, 4]);
+    }};
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+fn test_fair_release_serves_waiters() {
+    test_fair_release!(arc_mutex, "fair-mutex");
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_fair_read_release_serves_writers() {
+    test_fair_release!(arc_rwlock, "fair-rwlock");
+}
@@ -0,0 +1,237 @@
+//! C interface over an opaque pointer handle, so C and C++ plugins can retain and mutate state
+//! owned by the Rust side.
+//!
+//! Rust code hands out a handle with `SharedPtrHandle::new`, or C code creates one around its own
+//! data with `shared_ptr_new`. The value is only reachable inside `shared_ptr_read` and
+//! `shared_ptr_write` callbacks, which run with the lock held and receive a pointer to the value.
+//! For Rust-owned values the layout of `T` is the caller's contract, so those values are normally
+//! `#[repr(C)]`. Handles use the `arc_rwlock` backend, so concurrent reads from several threads
+//! proceed in parallel.
+//!
+//! Every function is `extern "C"` with plain pointer arguments so cbindgen can generate the
+//! header. A panic inside any of them, such as a failed invariant, aborts the process.
+use std::os::raw::c_void;
+
+use crate::arc_rwlock::SharedPtr;
+
+/// Callback invoked with the value under the read lock, plus the caller's context pointer.
+pub type ReadCallback = extern "C" fn(value: *const c_void, context: *mut c_void);
+
+/// Callback invoked with the value under the write lock, plus the caller's context pointer.
+pub type WriteCallback = extern "C" fn(value: *mut c_void, context: *mut c_void);
+
+/// Frees a C value once the last handle to it is dropped.
+pub type DropCallback = extern "C" fn(value: *mut c_void);
+
+trait Erased: Send + Sync {
+    fn read(&self, f: &mut dyn FnMut(*const c_void));
+
+    fn write(&self, f: &mut dyn FnMut(*mut c_void));
+
+    fn clone_box(&self) -> Box<dyn Erased>;
+}
+
+impl<T: Send + Sync + 'static> Erased for SharedPtr<T> {
+    fn read(&self, f: &mut dyn FnMut(*const c_void)) {
+        f(&*self.read() as *const T as *const c_void)
+    }
+
+    fn write(&self, f: &mut dyn FnMut(*mut c_void)) {
+        f(&mut *self.write() as *mut T as *mut c_void)
+    }
+
+    fn clone_box(&self) -> Box<dyn Erased> {
+        Box::new(self.clone())
+    }
+}
+
+/// A value owned by C code.
+struct Foreign {
+    value: *mut c_void,
+    drop: Option<DropCallback>,
+}
+
+// SAFETY: `shared_ptr_new` requires C values to be usable from any thread under the lock.
+unsafe impl Send for Foreign {}
+unsafe impl Sync for Foreign {}
+
+impl Drop for Foreign {
+    fn drop(&mut self) {
+        if let Some(drop) = self.drop {
+            drop(self.value)
+        }
+    }
+}
+
+/// A pointer to a C value. Unlike for Rust values, callbacks are passed the C value itself
+/// rather than the `Foreign` that owns it.
+#[derive(Clone)]
+struct ForeignPtr(SharedPtr<Foreign>);
+
+impl Erased for ForeignPtr {
+    fn read(&self, f: &mut dyn FnMut(*const c_void)) {
+        f(self.0.read().value)
+    }
+
+    fn write(&self, f: &mut dyn FnMut(*mut c_void)) {
+        f(self.0.write().value)
+    }
+
+    fn clone_box(&self) -> Box<dyn Erased> {
+        Box::new(self.clone())
+    }
+}
+
+/// Opaque handle to a shared value. Each handle is one strong reference and must be released
+/// with `shared_ptr_drop`.
+pub struct SharedPtrHandle(Box<dyn Erased>);
+
+impl SharedPtrHandle {
+    /// Moves a strong reference to `ptr` into a new handle for C code to own.
+    pub fn new<T: Send + Sync + 'static>(ptr: SharedPtr<T>) -> *mut SharedPtrHandle {
+        Box::into_raw(Box::new(SharedPtrHandle(Box::new(ptr))))
+    }
+}
+
+impl std::fmt::Debug for SharedPtrHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("SharedPtrHandle")
+    }
+}
+
+/// Creates a handle that owns the C value `value`. `drop`, if not null, is called with `value`
+/// once the last handle is dropped.
+///
+/// # Safety
+///
+/// `value` must stay valid until `drop` is called and must be safe to access from any thread
+/// while the lock is held.
+#[no_mangle]
+pub unsafe extern "C" fn shared_ptr_new(
+    value: *mut c_void,
+    drop: Option<DropCallback>,
+) -> *mut SharedPtrHandle {
+    let ptr = ForeignPtr(SharedPtr::new(Foreign { value, drop }));
+    Box::into_raw(Box::new(SharedPtrHandle(Box::new(ptr))))
+}
+
+/// Returns a new handle to the same value.
+///
+/// # Safety
+///
+/// `handle` must be a live handle returned by this module.
+#[no_mangle]
+pub unsafe extern "C" fn shared_ptr_clone(handle: *const SharedPtrHandle) -> *mut SharedPtrHandle {
+    Box::into_raw(Box::new(SharedPtrHandle((*handle).0.clone_box())))
+}
+
+/// Calls `callback` with the value and `context` while holding the read lock.
+///
+/// # Safety
+///
+/// `handle` must be a live handle returned by this module. The value pointer passed to
+/// `callback` must not be written through or used after it returns.
+#[no_mangle]
+pub unsafe extern "C" fn shared_ptr_read(
+    handle: *const SharedPtrHandle,
+    callback: ReadCallback,
+    context: *mut c_void,
+) {
+    (*handle).0.read(&mut |value| callback(value, context))
+}
+
+/// Calls `callback` with the value and `context` while holding the write lock.
+///
+/// # Safety
+///
+/// `handle` must be a live handle returned by this module. The value pointer passed to
+/// `callback` must not be used after it returns.
+#[no_mangle]
+pub unsafe extern "C" fn shared_ptr_write(
+    handle: *const SharedPtrHandle,
+    callback: WriteCallback,
+    context: *mut c_void,
+) {
+    (*handle).0.write(&mut |value| callback(value, context))
+}
+
+/// Releases a handle. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle returned by this module, and is dangling afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn shared_ptr_drop(handle: *mut SharedPtrHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[test]
+fn test_ffi_round_trip() {
+    #[repr(C)]
+    struct Counter {
+        count: u32,
+    }
+
+    extern "C" fn increment(value: *mut c_void, context: *mut c_void) {
+        unsafe { (*(value as *mut Counter)).count += *(context as *const u32) }
+    }
+
+    extern "C" fn load(value: *const c_void, context: *mut c_void) {
+        unsafe { *(context as *mut u32) = (*(value as *const Counter)).count }
+    }
+
+    let counter = SharedPtr::new(Counter { count: 0 });
+    let handle = SharedPtrHandle::new(counter.clone());
+    unsafe {
+        let clone = shared_ptr_clone(handle);
+        shared_ptr_drop(handle);
+        let mut step = 2u32;
+        shared_ptr_write(clone, increment, &mut step as *mut u32 as *mut c_void);
+
+        let mut seen = 0u32;
+        shared_ptr_read(clone, load, &mut seen as *mut u32 as *mut c_void);
+        assert_eq!(seen, 2);
+        shared_ptr_drop(clone);
+    }
+    assert_eq!(counter.read().count, 2);
+}
+
+#[test]
+fn test_ffi_foreign_value() {
+    use std::sync::atomic::{
+        AtomicBool,
+        Ordering,
+    };
+
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn double(value: *mut c_void, _: *mut c_void) {
+        unsafe { *(value as *mut u64) *= 2 }
+    }
+
+    extern "C" fn load(value: *const c_void, context: *mut c_void) {
+        unsafe { *(context as *mut u64) = *(value as *const u64) }
+    }
+
+    extern "C" fn free(value: *mut c_void) {
+        drop(unsafe { Box::from_raw(value as *mut u64) });
+        DROPPED.store(true, Ordering::SeqCst);
+    }
+
+    let value = Box::into_raw(Box::new(21u64)) as *mut c_void;
+    unsafe {
+        let handle = shared_ptr_new(value, Some(free));
+        shared_ptr_write(handle, double, std::ptr::null_mut());
+        let mut seen = 0u64;
+        shared_ptr_read(handle, load, &mut seen as *mut u64 as *mut c_void);
+        assert_eq!(seen, 42);
+
+        let clone = shared_ptr_clone(handle);
+        shared_ptr_drop(handle);
+        assert!(!DROPPED.load(Ordering::SeqCst));
+        shared_ptr_drop(clone);
+    }
+    assert!(DROPPED.load(Ordering::SeqCst));
+}
@@ -0,0 +1,218 @@
+//! Guards projected onto part of a pointer's value.
+//!
+//! `SharedPtr::read_field` and `SharedPtr::write_field` lock the whole value but hand out access
+//! to a single field. The guard is kept alongside a pointer to the field, which stays valid
+//! because every guard in this crate derefs into the pointer's heap allocation: moving the guard
+//! never moves the value it locks. `StableGuard` marks the guards this holds for.
+//!
+//! The projected guards are invariant in `V`, like `&mut V`. Otherwise a guard onto a
+//! `&'static str` could be passed off as one onto a shorter-lived `&str` and used to store a
+//! dangling reference into the locked value:
+//!
+//! ```compile_fail
+//! use shared_ptrs::arc_rwlock::FieldRefMut;
+//!
+//! fn shorten<'a, 'b>(field: FieldRefMut<'a, (), &'static str>) -> FieldRefMut<'a, (), &'b str> {
+//!     field
+//! }
+//! ```
+use std::marker::PhantomData;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+/// A guard that derefs to a location which does not move when the guard itself is moved.
+///
+/// # Safety
+///
+/// Implementors must deref, mutably or not, to the same address for as long as they are alive,
+/// wherever they are moved to.
+pub unsafe trait StableGuard: Deref {}
+
+unsafe impl<T: ?Sized> StableGuard for core::cell::Ref<'_, T> {}
+
+unsafe impl<T: ?Sized> StableGuard for core::cell::RefMut<'_, T> {}
+
+unsafe impl<T: ?Sized> StableGuard for crate::deps::parking_lot::MutexGuard<'_, T> {}
+
+unsafe impl<T: ?Sized> StableGuard for crate::deps::parking_lot::RwLockReadGuard<'_, T> {}
+
+unsafe impl<T: ?Sized> StableGuard for crate::deps::parking_lot::RwLockWriteGuard<'_, T> {}
+
+/// Shared access to a `V` inside the value locked by the read guard `G`.
+pub struct FieldGuard<G, V: ?Sized> {
+    // Declared before `guard`, though it has no drop glue: it must never outlive the lock.
+    field: NonNull<V>,
+    guard: G,
+    _invariant: PhantomData<*mut V>,
+}
+
+impl<G: StableGuard> FieldGuard<G, G::Target> {
+    /// Wraps the whole value locked by `guard`, to be narrowed with `map`.
+    pub fn new(guard: G) -> Self {
+        FieldGuard {
+            field: NonNull::from(&*guard),
+            guard,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<G: StableGuard, V: ?Sized> FieldGuard<G, V> {
+    /// Narrows the projection further.
+    pub fn map<U: ?Sized>(self, project: impl FnOnce(&V) -> &U) -> FieldGuard<G, U> {
+        let field = NonNull::from(project(&*self));
+        FieldGuard {
+            field,
+            guard: self.guard,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<G: Deref, V: ?Sized> Deref for FieldGuard<G, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: `field` was derived from a shared borrow of the locked value, which stays
+        // locked and in place for as long as `guard` is held.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+impl<G: Deref, V> std::fmt::Debug for FieldGuard<G, V>
+where
+    V: ?Sized + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to a `V` inside the value locked by the write guard `G`.
+pub struct FieldGuardMut<G, V: ?Sized> {
+    field: NonNull<V>,
+    guard: G,
+    _invariant: PhantomData<*mut V>,
+}
+
+impl<G: StableGuard + DerefMut> FieldGuardMut<G, G::Target> {
+    /// Wraps the whole value locked by `guard`, to be narrowed with `map`.
+    pub fn new(mut guard: G) -> Self {
+        FieldGuardMut {
+            field: NonNull::from(&mut *guard),
+            guard,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<G: StableGuard + DerefMut, V: ?Sized> FieldGuardMut<G, V> {
+    /// Narrows the projection further.
+    pub fn map<U: ?Sized>(mut self, project: impl FnOnce(&mut V) -> &mut U) -> FieldGuardMut<G, U> {
+        let field = NonNull::from(project(&mut *self));
+        FieldGuardMut {
+            field,
+            guard: self.guard,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<G: DerefMut, V: ?Sized> Deref for FieldGuardMut<G, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: see `deref_mut`.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+impl<G: DerefMut, V: ?Sized> DerefMut for FieldGuardMut<G, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        // SAFETY: `field` was derived from an exclusive borrow of the locked value, which stays
+        // locked and in place for as long as `guard` is held, and `guard` is not touched again
+        // until it is dropped.
+        unsafe { self.field.as_mut() }
+    }
+}
+
+impl<G: DerefMut, V> std::fmt::Debug for FieldGuardMut<G, V>
+where
+    V: ?Sized + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to one of two disjoint parts of the value locked by the write guard `G`,
+/// created by `WriteGuard::map_split`. The lock is released once both parts are dropped.
+///
+/// Invariant in `V` for the same reason as `FieldGuardMut`:
+///
+/// ```compile_fail
+/// use shared_ptrs::arc_rwlock::SplitRefMut;
+///
+/// fn shorten<'a, 'b>(part: SplitRefMut<'a, (), &'static str>) -> SplitRefMut<'a, (), &'b str> {
+///     part
+/// }
+/// ```
+pub struct SplitGuardMut<G, V: ?Sized> {
+    field: NonNull<V>,
+    // Shared by both parts and never dereferenced again, only dropped.
+    _guard: Arc<G>,
+    _invariant: PhantomData<*mut V>,
+}
+
+impl<G: StableGuard + DerefMut, V: ?Sized> SplitGuardMut<G, V> {
+    pub(crate) fn split<U: ?Sized>(
+        mut guard: G,
+        project: impl FnOnce(&mut G::Target) -> (&mut V, &mut U),
+    ) -> (Self, SplitGuardMut<G, U>) {
+        let (first, second) = project(&mut *guard);
+        let (first, second) = (NonNull::from(first), NonNull::from(second));
+        let guard = Arc::new(guard);
+        let first = SplitGuardMut {
+            field: first,
+            _guard: guard.clone(),
+            _invariant: PhantomData,
+        };
+        let second = SplitGuardMut {
+            field: second,
+            _guard: guard,
+            _invariant: PhantomData,
+        };
+        (first, second)
+    }
+}
+
+impl<G: DerefMut, V: ?Sized> Deref for SplitGuardMut<G, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: see `deref_mut`.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+impl<G: DerefMut, V: ?Sized> DerefMut for SplitGuardMut<G, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        // SAFETY: both parts were derived from one exclusive borrow of the locked value, so they
+        // don't overlap, and the value stays locked and in place until the last part drops
+        // `_guard`.
+        unsafe { self.field.as_mut() }
+    }
+}
+
+impl<G: DerefMut, V> std::fmt::Debug for SplitGuardMut<G, V>
+where
+    V: ?Sized + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
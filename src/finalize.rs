@@ -0,0 +1,43 @@
+//! Closures run when a pointer's last strong reference is dropped.
+use crate::deps::parking_lot::Mutex;
+
+pub(crate) trait Finalizer {
+    fn finalize(self: Box<Self>);
+}
+
+impl<F: FnOnce()> Finalizer for F {
+    fn finalize(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+/// Runs its finalizers, most recently registered first, when dropped along with the allocation.
+pub(crate) struct Finalizers<F: ?Sized + Finalizer>(Mutex<Vec<Box<F>>>);
+
+impl<F: ?Sized + Finalizer> Finalizers<F> {
+    pub(crate) fn new() -> Self {
+        Finalizers(Mutex::new(Vec::new()))
+    }
+
+    pub(crate) fn push(&self, finalizer: Box<F>) {
+        self.0.lock().push(finalizer);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().is_empty()
+    }
+
+    /// Takes the finalizers without running them, oldest first.
+    #[cfg(all(feature = "rc", any(feature = "mutex", feature = "rwlock")))]
+    pub(crate) fn into_vec(mut self) -> Vec<Box<F>> {
+        std::mem::take(self.0.get_mut())
+    }
+}
+
+impl<F: ?Sized + Finalizer> Drop for Finalizers<F> {
+    fn drop(&mut self) {
+        while let Some(finalizer) = self.0.get_mut().pop() {
+            finalizer.finalize();
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Read-only handles for values that stop changing.
+
+macro_rules! define_frozen {
+    ($ptr:ident $(, $send:ident)?) => {
+        /// An immutable handle produced by `SharedPtr::freeze`. Reads go straight to the value
+        /// without touching a lock, and clones share the allocation.
+        pub struct Frozen<T: ?Sized> {
+            // The `on_last_drop` finalizers taken over from the pointer, shared by the clones and
+            // run when the last one is dropped. Declared first so they run before the value is
+            // dropped.
+            finalizers: Option<
+                $ptr<crate::finalize::Finalizers<dyn crate::finalize::Finalizer $(+ $send)?>>,
+            >,
+            value: $ptr<T>,
+        }
+
+        impl<T: ?Sized> Frozen<T> {
+            pub fn read(&self) -> &T {
+                &self.value
+            }
+
+            /// Returns the underlying pointer, or the handle unchanged if it carries
+            /// `on_last_drop` finalizers, which could then no longer run when the value is
+            /// dropped.
+            pub fn try_into_inner(self) -> Result<$ptr<T>, Self> {
+                match self.finalizers {
+                    None => Ok(self.value),
+                    Some(_) => Err(self),
+                }
+            }
+        }
+
+        impl<T> SharedPtr<T> {
+            /// Converts the pointer into an immutable `Frozen` handle, cloning the value if
+            /// other pointers to it are still alive.
+            pub fn freeze(self) -> Frozen<T>
+            where
+                T: Clone,
+            {
+                self.try_freeze()
+                    .unwrap_or_else(|shared| Frozen::from($ptr::new(T::clone(&shared.read()))))
+            }
+
+            /// Converts the pointer into an immutable `Frozen` handle if it is the only strong
+            /// pointer to the value, and returns it unchanged otherwise. The `on_last_drop`
+            /// finalizers move to the handle and run when its last clone is dropped.
+            pub fn try_freeze(self) -> Result<Frozen<T>, Self> {
+                let (value, finalizers) = self.try_unwrap_parts()?;
+                Ok(Frozen {
+                    finalizers: (!finalizers.is_empty()).then(|| $ptr::new(finalizers)),
+                    value: $ptr::new(value),
+                })
+            }
+        }
+
+        impl<T: ?Sized> std::ops::Deref for Frozen<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.value
+            }
+        }
+
+        impl<T: ?Sized> Clone for Frozen<T> {
+            fn clone(&self) -> Self {
+                Frozen {
+                    finalizers: self.finalizers.clone(),
+                    value: self.value.clone(),
+                }
+            }
+        }
+
+        impl<T: ?Sized> From<$ptr<T>> for Frozen<T> {
+            fn from(ptr: $ptr<T>) -> Self {
+                Frozen {
+                    finalizers: None,
+                    value: ptr,
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for Frozen<T>
+        where
+            T: ?Sized + std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("Frozen").field(&&*self.value).finish()
+            }
+        }
+
+        impl<T> std::cmp::PartialEq for Frozen<T>
+        where
+            T: ?Sized + PartialEq,
+        {
+            fn eq(&self, other: &Self) -> bool {
+                *self.value == *other.value
+            }
+        }
+
+        impl<T> std::cmp::Eq for Frozen<T> where T: ?Sized + Eq {}
+
+        impl<T> std::hash::Hash for Frozen<T>
+        where
+            T: ?Sized + std::hash::Hash,
+        {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.value.hash(state)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<T> crate::deps::serde::ser::Serialize for Frozen<T>
+        where
+            T: ?Sized + crate::deps::serde::ser::Serialize,
+        {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: crate::deps::serde::Serializer,
+            {
+                self.value.serialize(serializer)
+            }
+        }
+
+        #[test]
+        fn test_freeze() {
+            let unique = SharedPtr::new(vec![1u32]);
+            unique.write().push(2);
+            assert_eq!(*unique.freeze().read(), [1, 2]);
+
+            let shared = SharedPtr::new(vec![1u32]);
+            let other = shared.clone();
+            let shared = shared.try_freeze().unwrap_err();
+            let frozen = shared.freeze();
+            other.write().push(3);
+            assert_eq!(*frozen, [1]);
+
+            assert!(frozen.try_into_inner().is_ok());
+
+            let finalized = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let finalizing = SharedPtr::new(vec![1u32]);
+            finalizing.on_last_drop({
+                let finalized = finalized.clone();
+                move || finalized.store(true, std::sync::atomic::Ordering::SeqCst)
+            });
+            let frozen = finalizing.freeze();
+            let alias = frozen.clone();
+            drop(frozen);
+            let alias = alias.try_into_inner().unwrap_err();
+            assert!(!finalized.load(std::sync::atomic::Ordering::SeqCst));
+            assert_eq!(*alias, [1]);
+            drop(alias);
+            assert!(finalized.load(std::sync::atomic::Ordering::SeqCst));
+        }
+    };
+}
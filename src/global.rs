@@ -0,0 +1,97 @@
+//! Lazily initialized global pointers. See `static_shared!`.
+use std::sync::OnceLock;
+
+/// A pointer stored in a `static` and created on first use. Derefs to the pointer, so a global
+/// has the same `read()`/`write()` surface as a local one.
+pub struct Global<P> {
+    cell: OnceLock<P>,
+    init: fn() -> P,
+}
+
+impl<P> Global<P> {
+    pub const fn new(init: fn() -> P) -> Self {
+        Global {
+            cell: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// Creates the pointer if it doesn't exist yet.
+    pub fn force(&self) -> &P {
+        self.cell.get_or_init(self.init)
+    }
+}
+
+impl<P> std::ops::Deref for Global<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        self.force()
+    }
+}
+
+impl<P: std::fmt::Debug> std::fmt::Debug for Global<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.cell.get() {
+            Some(ptr) => std::fmt::Debug::fmt(ptr, f),
+            None => f.write_str("Global(<uninit>)"),
+        }
+    }
+}
+
+/// Declares global pointers that are created on first use.
+///
+/// ```ignore
+/// static_shared! {
+///     pub static CONFIG: arc_rwlock::SharedPtr<Config> = Config::default();
+/// }
+///
+/// CONFIG.write().verbose = true;
+/// ```
+///
+/// The initializer is an expression of the value type and is evaluated at most once. Only the
+/// thread-safe backends can be used, since statics must be `Sync`.
+#[macro_export]
+macro_rules! static_shared {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::global::Global<$ty> =
+            $crate::global::Global::new(|| <$ty>::from($init));
+
+        $crate::static_shared!($($rest)*);
+    };
+}
+
+#[test]
+#[cfg(all(feature = "mutex", feature = "rwlock"))]
+fn test_static_shared() {
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    use crate::arc_rwlock::SharedPtr;
+
+    static INITS: AtomicUsize = AtomicUsize::new(0);
+
+    crate::static_shared! {
+        static NAMES: SharedPtr<Vec<&'static str>> = {
+            INITS.fetch_add(1, Ordering::SeqCst);
+            vec!["a"]
+        };
+        static COUNT: crate::arc_mutex::SharedPtr<u32> = 0;
+    }
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| std::thread::spawn(|| NAMES.write().push("b")))
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    *COUNT.write() += 1;
+
+    assert_eq!(NAMES.read().len(), 5);
+    assert_eq!(*COUNT.read(), 1);
+    assert_eq!(INITS.load(Ordering::SeqCst), 1);
+}
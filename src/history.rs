@@ -0,0 +1,162 @@
+//! Undo/redo history layered over the backend `SharedPtr`s.
+use std::collections::VecDeque;
+
+/// Bounded undo and redo stacks of previous values.
+pub(crate) struct Timeline<T> {
+    past: VecDeque<T>,
+    future: Vec<T>,
+    capacity: usize,
+}
+
+impl<T> Timeline<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Timeline {
+            past: VecDeque::new(),
+            future: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Records `previous` as an undo step. A new edit invalidates anything that was undone.
+    pub(crate) fn record(&mut self, previous: T) {
+        self.future.clear();
+        self.push_past(previous);
+    }
+
+    pub(crate) fn undo(&mut self, current: &mut T) -> bool {
+        match self.past.pop_back() {
+            Some(previous) => {
+                self.future.push(std::mem::replace(current, previous));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn redo(&mut self, current: &mut T) -> bool {
+        match self.future.pop() {
+            Some(next) => {
+                let previous = std::mem::replace(current, next);
+                self.push_past(previous);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.past.is_empty()
+    }
+
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.future.is_empty()
+    }
+
+    fn push_past(&mut self, previous: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.past.len() == self.capacity {
+            self.past.pop_front();
+        }
+        self.past.push_back(previous);
+    }
+}
+
+macro_rules! define_shared_history {
+    ($read_guard:ident) => {
+        /// A `SharedPtr` that snapshots its value on every `write()`, keeping up to `capacity`
+        /// undo steps.
+        ///
+        /// Clones share both the value and the history.
+        pub struct SharedHistory<T> {
+            value: SharedPtr<T>,
+            timeline: SharedPtr<crate::history::Timeline<T>>,
+        }
+
+        impl<T: Clone> SharedHistory<T> {
+            pub fn new(init: T, capacity: usize) -> Self {
+                SharedHistory {
+                    value: SharedPtr::new(init),
+                    timeline: SharedPtr::new(crate::history::Timeline::new(capacity)),
+                }
+            }
+
+            pub fn read(&self) -> $read_guard<'_, T> {
+                self.value.read()
+            }
+
+            /// Records the current value as an undo step and returns exclusive access to it.
+            #[track_caller]
+            pub fn write(&self) -> WriteGuard<'_, T> {
+                let guard = self.value.write();
+                self.timeline.write().record(T::clone(&guard));
+                guard
+            }
+
+            /// Restores the value from before the last write. Returns false if there is nothing
+            /// to undo.
+            pub fn undo(&self) -> bool {
+                let mut guard = self.value.write();
+                self.timeline.write().undo(&mut guard)
+            }
+
+            /// Reapplies the last undone write. Returns false if there is nothing to redo.
+            pub fn redo(&self) -> bool {
+                let mut guard = self.value.write();
+                self.timeline.write().redo(&mut guard)
+            }
+
+            pub fn can_undo(&self) -> bool {
+                self.timeline.read().can_undo()
+            }
+
+            pub fn can_redo(&self) -> bool {
+                self.timeline.read().can_redo()
+            }
+        }
+
+        impl<T> Clone for SharedHistory<T> {
+            fn clone(&self) -> Self {
+                SharedHistory {
+                    value: self.value.clone(),
+                    timeline: self.timeline.clone(),
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for SharedHistory<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_struct("SharedHistory")
+                    .field("value", &self.value)
+                    .finish()
+            }
+        }
+
+        #[test]
+        fn test_history_undo_redo() {
+            let doc = SharedHistory::new(String::new(), 2);
+            assert!(!doc.undo());
+
+            for word in ["a", "b", "c"] {
+                doc.write().push_str(word);
+            }
+            assert_eq!(*doc.read(), "abc");
+
+            assert!(doc.undo());
+            assert!(doc.clone().undo());
+            assert_eq!(*doc.read(), "a");
+            assert!(!doc.can_undo());
+
+            assert!(doc.redo());
+            assert_eq!(*doc.read(), "ab");
+
+            doc.write().push('!');
+            assert!(!doc.can_redo());
+            assert_eq!(*doc.read(), "ab!");
+        }
+    };
+}
@@ -0,0 +1,164 @@
+//! Configuration that reloads itself when its file changes.
+//!
+//! `watch` loads a file into an `arc_rwlock::SharedPtr` and then polls the file's modification
+//! time and size on a background thread. When either changes the file is decoded again with a
+//! `persist::Format` and stored under the write lock, so every clone sees it and `changes()`
+//! subscribers are notified like for any other write. A file that fails to read or decode leaves
+//! the current value in place.
+//!
+//! ```ignore
+//! let config = SharedPtr::new(Config::default());
+//! let _watcher = hotreload::watch("app.json", Json, &config, Duration::from_secs(1))?;
+//! let mut updates = config.changes();
+//! ```
+use std::fs;
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use crate::arc_rwlock::SharedPtr;
+use crate::deps::parking_lot::Mutex;
+use crate::deps::serde::de::DeserializeOwned;
+use crate::persist::Format;
+
+// What a change is detected by.
+type Stamp = (Option<SystemTime>, u64);
+
+fn stamp(path: &Path) -> io::Result<Stamp> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.modified().ok(), metadata.len()))
+}
+
+fn load<T, F>(path: &Path, format: &F, ptr: &SharedPtr<T>) -> io::Result<()>
+where
+    T: DeserializeOwned,
+    F: Format,
+{
+    let value = format.decode(&fs::read(path)?)?;
+    *ptr.write() = value;
+    Ok(())
+}
+
+/// Loads `path` into `ptr`, failing if that does, and then reloads it every time the file
+/// changes, checking every `interval`, until the returned handle is dropped.
+pub fn watch<T, F>(
+    path: impl Into<PathBuf>,
+    format: F,
+    ptr: &SharedPtr<T>,
+    interval: Duration,
+) -> io::Result<Watcher>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+    F: Format,
+{
+    let path = path.into();
+    let mut last = stamp(&path)?;
+    load(&path, &format, ptr)?;
+
+    let (stop, stopped) = mpsc::channel::<()>();
+    let errors = Arc::new(Mutex::new(None));
+    let last_error = errors.clone();
+    let ptr = ptr.clone();
+    let thread = std::thread::Builder::new()
+        .name("shared-ptrs-hotreload".into())
+        .spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let result = stamp(&path).and_then(|current| {
+                    if current == last {
+                        return Ok(());
+                    }
+                    last = current;
+                    load(&path, &format, &ptr)
+                });
+                if let Err(error) = result {
+                    *last_error.lock() = Some(error);
+                }
+            }
+        })
+        .expect("failed to spawn the hotreload thread");
+    Ok(Watcher {
+        stop: Some(stop),
+        thread: Some(thread),
+        errors,
+    })
+}
+
+/// Handle to a background file watcher, see `watch`. Dropping it stops the watching.
+#[derive(Debug)]
+pub struct Watcher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    errors: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Watcher {
+    /// Returns the error from the most recent failed reload, if any, and clears it.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.errors.lock().take()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_reload_on_change() {
+    struct Lines;
+
+    impl Format for Lines {
+        fn encode<T: crate::deps::serde::Serialize + ?Sized>(&self, _: &T) -> io::Result<Vec<u8>> {
+            Err(io::Error::other("the test format only decodes"))
+        }
+
+        fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+            use crate::test_value::{
+                from_value,
+                Value,
+            };
+
+            let text = std::str::from_utf8(bytes).map_err(io::Error::other)?;
+            let lines = text.lines().map(|line| Value::Str(line.to_owned())).collect();
+            from_value(Value::Seq(lines)).map_err(io::Error::other)
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("shared-ptrs-hotreload-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("hosts");
+    fs::write(&path, "a\nb\n").unwrap();
+
+    let hosts = SharedPtr::new(Vec::<String>::new());
+    let mut changes = hosts.changes();
+    let watcher = watch(&path, Lines, &hosts, Duration::from_millis(5)).unwrap();
+    assert_eq!(*hosts.read(), ["a", "b"]);
+    assert_eq!(changes.try_next().unwrap(), ["a", "b"]);
+
+    fs::write(&path, "a\nb\nc\n").unwrap();
+    while hosts.read().len() < 3 {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(changes.try_next().unwrap(), ["a", "b", "c"]);
+
+    fs::remove_file(&path).unwrap();
+    while watcher.take_error().is_none() {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(hosts.read().len(), 3);
+
+    drop(watcher);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,55 @@
+//! Method-call construction of pointers, for builder-style code where `SharedPtr::new(...)`
+//! breaks up a chain.
+//!
+//! ```ignore
+//! use shared_ptrs::into_shared::IntoShared;
+//!
+//! let config = Config::default().with_retries(3).shared_rwlock();
+//! let cache: arc_mutex::SharedPtr<_> = HashMap::new().shared();
+//! ```
+#[cfg(feature = "mutex")]
+use crate::arc_mutex;
+#[cfg(feature = "rwlock")]
+use crate::arc_rwlock;
+#[cfg(feature = "rc")]
+use crate::rc_refcell;
+
+/// Wraps any value in a pointer. Implemented for every type.
+pub trait IntoShared: Sized {
+    /// Wraps the value in whichever pointer the result is inferred to be.
+    fn shared<P: From<Self>>(self) -> P {
+        P::from(self)
+    }
+
+    #[cfg(feature = "rc")]
+    /// Wraps the value in a single threaded `rc_refcell::SharedPtr`.
+    fn shared_local(self) -> rc_refcell::SharedPtr<Self> {
+        rc_refcell::SharedPtr::new(self)
+    }
+
+    #[cfg(feature = "mutex")]
+    /// Wraps the value in an `arc_mutex::SharedPtr`.
+    fn shared_mutex(self) -> arc_mutex::SharedPtr<Self> {
+        arc_mutex::SharedPtr::new(self)
+    }
+
+    #[cfg(feature = "rwlock")]
+    /// Wraps the value in an `arc_rwlock::SharedPtr`.
+    fn shared_rwlock(self) -> arc_rwlock::SharedPtr<Self> {
+        arc_rwlock::SharedPtr::new(self)
+    }
+}
+
+impl<T> IntoShared for T {}
+
+#[test]
+#[cfg(all(feature = "rc", feature = "mutex", feature = "rwlock"))]
+fn test_into_shared() {
+    let local = vec![1u32].shared_local();
+    local.write().push(2);
+    assert_eq!(*local.read(), [1, 2]);
+
+    let inferred: arc_mutex::SharedPtr<u32> = 3.shared();
+    assert_eq!(*inferred.read(), 3);
+    assert_eq!(*"rw".to_owned().shared_rwlock().read(), "rw");
+}
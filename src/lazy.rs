@@ -0,0 +1,104 @@
+//! Pointers whose value is built on first access.
+
+macro_rules! define_shared_lazy {
+    ($($bound:ident),*) => {
+        /// A pointer that runs its initializer the first time the value is read or written, with
+        /// the write lock held so concurrent first accesses wait for a single initialization.
+        /// Afterwards it behaves like a `SharedPtr`.
+        ///
+        /// Clones share the value and the pending initializer. If the initializer panics, every
+        /// later access panics too.
+        pub struct SharedLazy<T> {
+            value: SharedPtr<Option<T>>,
+            init: SharedPtr<Option<Box<dyn FnOnce() -> T $(+ $bound)*>>>,
+        }
+
+        impl<T> SharedLazy<T> {
+            pub fn new(init: impl FnOnce() -> T $(+ $bound)* + 'static) -> Self {
+                SharedLazy {
+                    value: SharedPtr::new(None),
+                    init: SharedPtr::new(Some(Box::new(init))),
+                }
+            }
+
+            pub fn read(&self) -> FieldRef<'_, Option<T>, T> {
+                loop {
+                    let value = self.value.read();
+                    if value.is_some() {
+                        return crate::field::FieldGuard::new(value).map(|v| v.as_ref().unwrap());
+                    }
+                    drop(value);
+                    self.force();
+                }
+            }
+
+            #[track_caller]
+            pub fn write(&self) -> FieldRefMut<'_, Option<T>, T> {
+                self.force();
+                self.value.write_field(|v| v.as_mut().unwrap())
+            }
+
+            pub fn is_initialized(&self) -> bool {
+                self.value.read().is_some()
+            }
+
+            /// Runs the initializer if it hasn't run yet.
+            pub fn force(&self) {
+                if self.is_initialized() {
+                    return;
+                }
+                let mut value = self.value.write();
+                if value.is_none() {
+                    let init = self
+                        .init
+                        .write()
+                        .take()
+                        .expect("SharedLazy initializer panicked on an earlier access");
+                    *value = Some(init());
+                }
+            }
+        }
+
+        impl<T> Clone for SharedLazy<T> {
+            fn clone(&self) -> Self {
+                SharedLazy {
+                    value: self.value.clone(),
+                    init: self.init.clone(),
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for SharedLazy<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match &*self.value.read() {
+                    Some(value) => f.debug_tuple("SharedLazy").field(value).finish(),
+                    None => f.write_str("SharedLazy(<uninit>)"),
+                }
+            }
+        }
+
+        #[test]
+        fn test_shared_lazy_initializes_once() {
+            use std::sync::atomic::{
+                AtomicUsize,
+                Ordering,
+            };
+
+            static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+            let lazy = SharedLazy::new(|| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                vec![1u32]
+            });
+            let other = lazy.clone();
+            assert!(!lazy.is_initialized());
+
+            other.write().push(2);
+            assert_eq!(*lazy.read(), [1, 2]);
+            assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        }
+    };
+}
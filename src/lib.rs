@@ -11,6 +11,35 @@
 //! * `arc_rwlock::SharedPtr`: The thread-safe shared pointer that provides interior mutability via
 //!   a parking_lot rwlock
 //!
+//! ## Features
+//!
+//! * `rc`, `mutex`, `rwlock` (default): the `rc_refcell`, `arc_mutex` and `arc_rwlock` backends.
+//!   Modules built on a backend, such as `bounded` on `arc_rwlock`, are compiled with it.
+//!   Only `mutex` and `rwlock` depend on parking_lot; with just `rc` the shared infrastructure,
+//!   such as the registry and observers, locks with `std::sync`
+//! * `serde` (default): `Serialize` and `Deserialize` for the pointers
+//! * `send_guard`: guards that can be sent to other threads, see below
+//! * `ops`: compound assignment operators (`ptr += 1`) that take the write lock
+//! * `shmem`: the `shmem` backend, a pointer into POSIX shared memory that several processes
+//!   can open (unix only)
+//!
+//! ## Thread safety
+//!
+//! The pointers are `Send` and `Sync` exactly when the lock they wrap is shared across threads
+//! safely, and generic code may rely on these bounds:
+//!
+//! * `rc_refcell::SharedPtr<T>` is never `Send` or `Sync`
+//! * `arc_mutex::SharedPtr<T>` is `Send + Sync` when `T: Send`
+//! * `arc_rwlock::SharedPtr<T>` is `Send + Sync` when `T: Send + Sync`, since readers on several
+//!   threads see the value at once
+//!
+//! Guards are not `Send` by default, so a guard held across an `.await` makes the future `!Send`
+//! and the mistake is caught at compile time. Code that hands guards to scoped threads can enable
+//! the `send_guard` feature, which makes the `arc_mutex` and `arc_rwlock` guards `Send` whenever
+//! the pointer itself is. `rc_refcell` guards are never `Send`.
+//! The feature is parking_lot's, so it also takes effect if any other crate in the build enables
+//! `parking_lot/send_guard`.
+//!
 //! ## Rationale
 //!
 //! Some of the issues we've had with different types of concurrency
@@ -31,231 +60,2244 @@
 //!
 //! Until then, the workaround is to Box your trait object. `SharedPtr<Box<dyn Trait>>` which will
 //! allow you to store the trait object at the cost of another level of indirection.
+//!
+//! Boxed trait objects serialize whenever `dyn Trait: Serialize`. The usual way to get there is
+//! to make `erased_serde::Serialize` a supertrait of `Trait` and invoke
+//! `erased_serde::serialize_trait_object!(Trait)`, after which `SharedPtr<Box<dyn Trait>>`
+//! serializes like any other pointer.
+//!
+//! Likewise they clone whenever `Box<dyn Trait>: Clone`, which dyn-clone provides: make
+//! `dyn_clone::DynClone` a supertrait of `Trait` and invoke
+//! `dyn_clone::clone_trait_object!(Trait)`. `deep_clone`, `transaction`, `changes` and the
+//! other features that copy the value then work on `SharedPtr<Box<dyn Trait>>`, cloning the
+//! trait object through its `clone_box`.
 #![allow(clippy::new_without_default)]
 #![warn(rustdoc::missing_crate_level_docs)]
 #![warn(missing_debug_implementations)]
 #![deny(warnings)]
+// Without a backend only the shared scaffolding is compiled, and most of it has no callers.
+#![cfg_attr(
+    not(any(feature = "rc", feature = "mutex", feature = "rwlock")),
+    allow(dead_code, unused_imports, unused_macros)
+)]
 mod deps {
-    pub use ::derive_more;
-    pub use ::owning_ref;
+    #[cfg(all(unix, feature = "shmem"))]
+    pub use ::libc;
+    #[cfg(feature = "parking_lot")]
     pub use ::parking_lot;
+    // Without the `mutex` and `rwlock` backends the shared infrastructure locks with `std`.
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) use crate::std_sync as parking_lot;
     #[cfg(feature = "serde")]
     pub use ::serde;
 }
+#[cfg(not(feature = "parking_lot"))]
+mod std_sync;
+
+#[macro_use]
+mod double_buffer;
+#[macro_use]
+mod frozen;
+#[macro_use]
+mod history;
+#[macro_use]
+mod lazy;
+#[macro_use]
+mod once;
+#[macro_use]
+mod split;
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+#[macro_use]
+mod static_ptr;
+#[macro_use]
+mod tuple;
+#[macro_use]
+mod uninit;
+#[macro_use]
+pub mod mapped;
+#[macro_use]
+pub mod revocable;
+#[macro_use]
+pub mod store;
+pub mod actor;
+pub mod async_access;
+pub mod atomic;
+pub mod audit;
+#[cfg(feature = "rwlock")]
+pub mod bounded;
+#[cfg(feature = "rwlock")]
+pub mod bravo;
+pub mod by_address;
+pub mod collections;
+#[cfg(any(
+    all(feature = "rc", feature = "mutex"),
+    all(feature = "rc", feature = "rwlock"),
+    all(feature = "mutex", feature = "rwlock"),
+))]
+mod compare;
+#[cfg(all(feature = "rc", any(feature = "mutex", feature = "rwlock")))]
+mod convert;
+#[cfg(feature = "rc")]
+pub mod cycles;
+#[cfg(all(feature = "mutex", feature = "rwlock"))]
+pub mod dyn_ptr;
+pub mod fairness;
+#[cfg(feature = "rwlock")]
+pub mod ffi;
+pub mod field;
+mod finalize;
+pub mod global;
+#[cfg(all(feature = "serde", feature = "rwlock"))]
+pub mod hotreload;
+pub mod into_shared;
+mod mailbox;
+pub mod observe;
+#[cfg(feature = "ops")]
+mod ops;
+pub mod padded;
+#[cfg(all(feature = "serde", any(feature = "mutex", feature = "rwlock")))]
+pub mod persist;
+#[cfg(feature = "rwlock")]
+pub mod persistent;
+#[cfg(feature = "rwlock")]
+pub mod read_mostly;
+pub mod recorder;
+#[cfg(feature = "rwlock")]
+pub mod refresh;
+pub mod registry;
+#[cfg(all(unix, feature = "shmem"))]
+pub mod shmem;
+pub mod stats;
+#[cfg(feature = "serde")]
+pub mod sharing;
+pub mod stm;
+#[cfg(all(test, feature = "serde"))]
+mod test_value;
+#[cfg(feature = "rwlock")]
+pub mod testing;
+pub mod tracker;
+#[cfg(feature = "serde")]
+pub mod try_serialize;
+
+/// Evaluates to whether `$ty` implements `$trait`, so tests can assert that a type does *not*.
+/// The inherent constant shadows the trait's fallback only when the bound holds.
+#[cfg(test)]
+macro_rules! implements {
+    ($ty:ty: $trait:path) => {{
+        #[allow(dead_code)]
+        trait Fallback {
+            const IMPLEMENTS: bool = false;
+        }
+
+        struct Probe<T: ?Sized>(std::marker::PhantomData<T>);
+
+        impl<T: ?Sized> Fallback for Probe<T> {}
+
+        #[allow(dead_code)]
+        impl<T: ?Sized + $trait> Probe<T> {
+            const IMPLEMENTS: bool = true;
+        }
+
+        <Probe<$ty>>::IMPLEMENTS
+    }};
+}
 
 macro_rules! define_shared_mut {
-    ($name:ident, $weak_name:ident, $ptr:ident, $weak_ptr:ident, $guard:ident, $read_fn:ident, $write_fn:ident, $read_guard:ident, $write_guard:ident) => {
-        #[derive(derive_more::From)]
-        pub struct $name<T: ?Sized>($ptr<$guard<T>>);
+    ($name:ident, $weak_name:ident, $ptr:ident, $weak_ptr:ident, $guard:ident, $read_fn:ident, $write_fn:ident, $read_guard:ident, $write_guard:ident $(, $send:ident)?) => {
+        pub(crate) struct Inner<T: ?Sized> {
+            name: Option<std::borrow::Cow<'static, str>>,
+            generation: u64,
+            dirty: Option<std::sync::atomic::AtomicBool>,
+            audit: Option<crate::audit::AuditLog<T>>,
+            invariant: crate::deps::parking_lot::Mutex<Option<fn(&T) -> Result<(), String>>>,
+            writer_thread: crate::deps::parking_lot::Mutex<Option<std::thread::ThreadId>>,
+            // Set once `invariant` or `writer_thread` is, so plain writes skip their locks.
+            has_invariant: std::sync::atomic::AtomicBool,
+            has_writer_thread: std::sync::atomic::AtomicBool,
+            version: std::sync::atomic::AtomicU64,
+            stats: Option<crate::stats::StatsCounters>,
+            fairness: crate::fairness::Fairness,
+            observers: crate::observe::Observers<Observer<T>>,
+            mailbox: Option<crate::mailbox::Mailbox<dyn FnOnce(&mut T) $(+ $send)?>>,
+            // Dropped, and so run, before `value`.
+            finalizers: crate::finalize::Finalizers<dyn crate::finalize::Finalizer $(+ $send)?>,
+            // Held only to unregister the allocation when it is dropped.
+            _tracked: Option<crate::tracker::Tracked>,
+            // Declared before `value` so the registry stops probing the lock before it is
+            // dropped. Only set once the allocation, and with it the lock, is in place.
+            registration: std::sync::OnceLock<crate::registry::Registration>,
+            value: $guard<T>,
+        }
+
+        impl<T> Inner<T> {
+            fn new(value: $guard<T>) -> Self {
+                Inner {
+                    name: None,
+                    generation: crate::by_address::next_generation(),
+                    dirty: None,
+                    audit: None,
+                    invariant: crate::deps::parking_lot::Mutex::new(None),
+                    writer_thread: crate::deps::parking_lot::Mutex::new(None),
+                    has_invariant: std::sync::atomic::AtomicBool::new(false),
+                    has_writer_thread: std::sync::atomic::AtomicBool::new(false),
+                    version: std::sync::atomic::AtomicU64::new(0),
+                    stats: None,
+                    fairness: crate::fairness::Fairness::default(),
+                    observers: crate::observe::Observers::new(),
+                    mailbox: None,
+                    finalizers: crate::finalize::Finalizers::new(),
+                    _tracked: crate::tracker::Tracked::register::<T>(),
+                    registration: std::sync::OnceLock::new(),
+                    value,
+                }
+            }
+        }
+
+        /// # Safety
+        ///
+        /// `lock` must point to a live `$guard<T>`.
+        unsafe fn probe_lock<T>(lock: *const ()) -> crate::registry::LockState {
+            lock_state(&*(lock as *const $guard<T>))
+        }
+
+        impl<T: ?Sized> Inner<T> {
+            fn lock_read(&self) -> $read_guard<'_, T> {
+                match &self.stats {
+                    None => read_lock(&self.value, self.fairness),
+                    Some(stats) => stats.acquire(
+                        false,
+                        || try_read_lock(&self.value, self.fairness),
+                        || read_lock(&self.value, self.fairness),
+                    ),
+                }
+            }
+
+            fn lock_write(&self) -> $write_guard<'_, T> {
+                match &self.stats {
+                    None => self.value.$write_fn(),
+                    Some(stats) => stats.acquire(
+                        true,
+                        || try_write_lock(&self.value),
+                        || self.value.$write_fn(),
+                    ),
+                }
+            }
+
+            /// Takes the write lock, starts the write's bookkeeping and applies the mutations
+            /// queued by `queue_write`, which thereby count as part of the write.
+            fn write(&self, location: &'static std::panic::Location<'static>) -> WriteGuard<'_, T> {
+                let guard = self.lock_write();
+                let pending = self.begin_write(location, &guard);
+                let mut guard = WriteGuard {
+                    inner: self,
+                    location,
+                    pending,
+                    guard: Some(guard),
+                };
+                if let Some(mailbox) = &self.mailbox {
+                    for mutation in mailbox.take() {
+                        mutation(&mut guard);
+                    }
+                }
+                guard
+            }
+
+            fn has_queued(&self) -> bool {
+                self.mailbox.as_ref().map_or(false, |mailbox| !mailbox.is_empty())
+            }
+
+            fn begin_write(
+                &self,
+                location: &'static std::panic::Location<'static>,
+                value: &T,
+            ) -> Option<crate::audit::PendingAudit> {
+                if cfg!(debug_assertions) {
+                    self.check_writer_thread(location);
+                }
+                self.version.fetch_add(1, std::sync::atomic::Ordering::Release);
+                if let Some(dirty) = &self.dirty {
+                    dirty.store(true, std::sync::atomic::Ordering::Release);
+                }
+                if let Some(registration) = self.registration.get() {
+                    registration.hold(location);
+                }
+                if let Some(stats) = &self.stats {
+                    stats.begin_hold();
+                }
+                self.audit.as_ref().map(|audit| audit.begin(location, value))
+            }
+
+            fn end_write(
+                &self,
+                location: &'static std::panic::Location<'static>,
+                pending: Option<crate::audit::PendingAudit>,
+                value: &T,
+            ) {
+                if let Some(registration) = self.registration.get() {
+                    registration.release();
+                }
+                if cfg!(debug_assertions) && !std::thread::panicking() {
+                    self.check_invariant(location, value);
+                }
+                if let (Some(audit), Some(pending)) = (&self.audit, pending) {
+                    audit.finish(pending, value);
+                }
+                self.observers.retain(|observer| observer(value));
+                if let Some(stats) = &self.stats {
+                    stats.end_hold(self.label(), location);
+                }
+            }
+
+            /// The name if there is one, otherwise the value type.
+            fn label(&self) -> &str {
+                self.name.as_deref().unwrap_or_else(|| std::any::type_name::<T>())
+            }
+
+            fn check_writer_thread(&self, location: &std::panic::Location<'_>) {
+                if !self.has_writer_thread.load(std::sync::atomic::Ordering::Acquire) {
+                    return;
+                }
+                let writer = *self.writer_thread.lock();
+                let current = std::thread::current().id();
+                if let Some(writer) = writer.filter(|writer| *writer != current) {
+                    panic!(
+                        "write to {} at {} on {:?}, but it is pinned to writer thread {:?}",
+                        self.label(),
+                        location,
+                        current,
+                        writer
+                    );
+                }
+            }
+
+            fn check_invariant(&self, location: &std::panic::Location<'_>, value: &T) {
+                if !self.has_invariant.load(std::sync::atomic::Ordering::Acquire) {
+                    return;
+                }
+                let check = *self.invariant.lock();
+                if let Some(Err(violation)) = check.map(|check| check(value)) {
+                    panic!(
+                        "invariant of {} violated by write at {}: {}",
+                        self.label(),
+                        location,
+                        violation
+                    );
+                }
+            }
+        }
+
+        pub struct $name<T: ?Sized>($ptr<Inner<T>>);
 
 
         impl<T: Sized> $name<T> {
             pub fn new(init: T) -> Self {
-                $name($ptr::new($guard::new(init)))
+                $name($ptr::new(Inner::new($guard::new(init))))
             }
-        }
 
-        impl<T: ?Sized> $name<T> {
-            pub fn read(&self) -> $read_guard<'_, T> {
-                self.0.deref().$read_fn()
+            /// Creates a pointer along with a weak pointer to it.
+            pub fn new_with_weak(init: T) -> (Self, $weak_name<T>) {
+                let ptr = $name::new(init);
+                let weak = $weak_name::downgrade(&ptr);
+                (ptr, weak)
+            }
+
+            /// Returns the value if this is the only strong pointer to it, and the pointer
+            /// unchanged otherwise.
+            pub fn try_unwrap(self) -> Result<T, Self> {
+                self.try_unwrap_parts().map(|(value, finalizers)| {
+                    drop(finalizers);
+                    value
+                })
+            }
+
+            /// Like `try_unwrap`, but hands back the `on_last_drop` finalizers instead of running
+            /// them, for conversions that keep the value alive.
+            #[allow(clippy::type_complexity)]
+            pub(crate) fn try_unwrap_parts(
+                self,
+            ) -> Result<
+                (T, crate::finalize::Finalizers<dyn crate::finalize::Finalizer $(+ $send)?>),
+                Self,
+            > {
+                // Keeps the allocation alive until the lock registration has been dropped.
+                let _allocation = $ptr::downgrade(&self.0);
+                match $ptr::try_unwrap(self.0) {
+                    Ok(inner) => {
+                        drop(inner.registration);
+                        let mut value = inner.value.into_inner();
+                        if let Some(mailbox) = &inner.mailbox {
+                            for mutation in mailbox.take() {
+                                mutation(&mut value);
+                            }
+                        }
+                        Ok((value, inner.finalizers))
+                    }
+                    Err(shared) => Err($name(shared)),
+                }
+            }
+
+            /// Takes back a pointer released by `into_raw`.
+            ///
+            /// # Safety
+            ///
+            /// `raw` must come from `into_raw` on a pointer of this exact type and backend, and
+            /// each `into_raw` may be matched by only one `from_raw`.
+            pub unsafe fn from_raw(raw: *const ()) -> Self {
+                $name($ptr::from_raw(raw.cast::<Inner<T>>()))
+            }
+
+            /// Creates a pointer with dirty tracking enabled. Every call to `write()` marks the
+            /// value as dirty until the flag is cleared with `take_dirty()`.
+            pub fn new_tracked(init: T) -> Self {
+                $name($ptr::new(Inner {
+                    dirty: Some(std::sync::atomic::AtomicBool::new(false)),
+                    ..Inner::new($guard::new(init))
+                }))
+            }
+
+            /// Creates a pointer with a human-readable name, such as `"render.scene"`, that
+            /// identifies it in `Debug` output and diagnostics instead of its type. Named
+            /// pointers are also listed by the lock `registry` while it is enabled.
+            pub fn new_named(name: impl Into<std::borrow::Cow<'static, str>>, init: T) -> Self {
+                $name::builder(init).name(name).build()
+            }
+
+            /// Starts building a pointer with several construction options at once, e.g.
+            /// `SharedPtr::builder(config).name("config").fair().with_stats().build()`.
+            pub fn builder(init: T) -> SharedPtrBuilder<T> {
+                SharedPtrBuilder {
+                    value: init,
+                    name: None,
+                    fairness: crate::fairness::Fairness::default(),
+                    stats: false,
+                    long_hold: None,
+                    tracked: false,
+                    audit: None,
+                    mailbox: false,
+                    finalizers: Vec::new(),
+                }
+            }
+
+            /// Creates a pointer that counts lock acquisitions, contention and time spent
+            /// waiting, retrievable with `stats()`. Every acquisition first tries the lock without
+            /// blocking, which is what makes contention observable.
+            pub fn new_with_stats(init: T) -> Self {
+                $name($ptr::new(Inner {
+                    stats: Some(Default::default()),
+                    ..Inner::new($guard::new(init))
+                }))
+            }
+
+            /// Creates a pointer whose lock follows `fairness` when readers and writers contend,
+            /// e.g. `Fairness::Fair` to stop a busy stream of readers from starving writers.
+            pub fn new_with_fairness(init: T, fairness: crate::fairness::Fairness) -> Self {
+                $name($ptr::new(Inner {
+                    fairness,
+                    ..Inner::new($guard::new(init))
+                }))
             }
 
-            pub fn write(&self) -> $write_guard<'_, T> {
-                self.0.deref().$write_fn()
+            /// Creates a pointer whose `queue_write` defers mutations instead of blocking on the
+            /// lock.
+            pub fn new_with_mailbox(init: T) -> Self {
+                $name($ptr::new(Inner {
+                    mailbox: Some(crate::mailbox::Mailbox::new()),
+                    ..Inner::new($guard::new(init))
+                }))
+            }
+
+            /// Creates a pointer that records the last `capacity` writes, retrievable with
+            /// `audit()`. If `describe` is given, each entry also carries the rendered value from
+            /// before and after the write.
+            pub fn new_audited(
+                init: T,
+                capacity: usize,
+                describe: Option<fn(&T) -> String>,
+            ) -> Self {
+                $name($ptr::new(Inner {
+                    audit: Some(crate::audit::AuditLog::new(capacity, describe)),
+                    ..Inner::new($guard::new(init))
+                }))
             }
         }
 
-        // TODO(dillybar): do we still need this?
-        #[allow(dead_code)]
-        impl<T> $name<T> {
-            pub(crate) fn as_ptr(&self) -> *const T {
-                <$ptr<$guard<T>>>::as_ptr(&self.0) as *const T
+        /// Collects construction options for a pointer, see `SharedPtr::builder`. Each option
+        /// does the same as the matching `new_*` constructor.
+        pub struct SharedPtrBuilder<T> {
+            value: T,
+            name: Option<std::borrow::Cow<'static, str>>,
+            fairness: crate::fairness::Fairness,
+            stats: bool,
+            long_hold: Option<std::time::Duration>,
+            tracked: bool,
+            audit: Option<usize>,
+            mailbox: bool,
+            finalizers: Vec<Box<dyn crate::finalize::Finalizer $(+ $send)?>>,
+        }
+
+        impl<T> SharedPtrBuilder<T> {
+            pub fn name(mut self, name: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+                self.name = Some(name.into());
+                self
+            }
+
+            pub fn fairness(mut self, fairness: crate::fairness::Fairness) -> Self {
+                self.fairness = fairness;
+                self
+            }
+
+            /// Shorthand for `fairness(Fairness::Fair)`.
+            pub fn fair(self) -> Self {
+                self.fairness(crate::fairness::Fairness::Fair)
+            }
+
+            /// Pads the value to a cache line, like `new_padded`.
+            pub fn padded(self) -> SharedPtrBuilder<crate::padded::CachePadded<T>> {
+                SharedPtrBuilder {
+                    value: crate::padded::CachePadded::new(self.value),
+                    name: self.name,
+                    fairness: self.fairness,
+                    stats: self.stats,
+                    long_hold: self.long_hold,
+                    tracked: self.tracked,
+                    audit: self.audit,
+                    mailbox: self.mailbox,
+                    finalizers: self.finalizers,
+                }
+            }
+
+            pub fn with_stats(mut self) -> Self {
+                self.stats = true;
+                self
+            }
+
+            /// Enables statistics and reports every `write()` guard held for longer than
+            /// `threshold` to the hook set with `stats::set_long_hold_hook`.
+            pub fn warn_long_holds(mut self, threshold: std::time::Duration) -> Self {
+                self.long_hold = Some(threshold);
+                self
+            }
+
+            /// Enables dirty tracking, like `new_tracked`.
+            pub fn tracked(mut self) -> Self {
+                self.tracked = true;
+                self
+            }
+
+            /// Records the last `capacity` writes, like `new_audited` without rendered values.
+            pub fn audited(mut self, capacity: usize) -> Self {
+                self.audit = Some(capacity);
+                self
+            }
+
+            pub fn with_mailbox(mut self) -> Self {
+                self.mailbox = true;
+                self
+            }
+
+            /// Registers a finalizer, like `SharedPtr::on_last_drop`.
+            pub fn on_last_drop(mut self, finalizer: impl FnOnce() $(+ $send)? + 'static) -> Self {
+                self.finalizers.push(Box::new(finalizer));
+                self
+            }
+
+            pub fn build(self) -> $name<T> {
+                let SharedPtrBuilder {
+                    value,
+                    name,
+                    fairness,
+                    stats,
+                    long_hold,
+                    tracked,
+                    audit,
+                    mailbox,
+                    finalizers,
+                } = self;
+                let ptr = $name($ptr::new(Inner {
+                    name,
+                    fairness,
+                    stats: match long_hold {
+                        Some(threshold) => {
+                            Some(crate::stats::StatsCounters::warning_after(threshold))
+                        }
+                        None => stats.then(Default::default),
+                    },
+                    dirty: tracked.then(|| std::sync::atomic::AtomicBool::new(false)),
+                    audit: audit.map(|capacity| crate::audit::AuditLog::new(capacity, None)),
+                    mailbox: mailbox.then(crate::mailbox::Mailbox::new),
+                    ..Inner::new($guard::new(value))
+                }));
+                if let Some(name) = ptr.0.name.as_deref() {
+                    if crate::registry::Registration::is_enabled() {
+                        // SAFETY: the lock is initialized and stays in place until `Inner`, and
+                        // with it the registration, is dropped; `try_unwrap` drops the
+                        // registration before anything else. Probing only inspects the lock's
+                        // state.
+                        let probe = unsafe {
+                            let lock = &ptr.0.value as *const $guard<T>;
+                            crate::registry::Probe::new(lock as *const (), probe_lock::<T>)
+                        };
+                        let _ = ptr.0.registration.set(register_lock(name, probe));
+                    }
+                }
+                for finalizer in finalizers {
+                    ptr.0.finalizers.push(finalizer);
+                }
+                ptr
             }
         }
 
-        impl<T> std::fmt::Debug for $name<T>
+        impl<T> std::fmt::Debug for SharedPtrBuilder<T>
         where
             T: std::fmt::Debug,
         {
             fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                f.debug_tuple("$name").field(&self.read()).finish()
+                f.debug_struct("SharedPtrBuilder")
+                    .field("value", &self.value)
+                    .field("name", &self.name)
+                    .field("fairness", &self.fairness)
+                    .field("stats", &self.stats)
+                    .field("long_hold", &self.long_hold)
+                    .field("tracked", &self.tracked)
+                    .field("audit", &self.audit)
+                    .field("mailbox", &self.mailbox)
+                    .field("finalizers", &self.finalizers.len())
+                    .finish()
             }
         }
 
-        #[cfg(feature = "serde")]
-        impl<'de, T> crate::deps::serde::de::Deserialize<'de> for $name<T>
-        where
-            T: Sized + crate::deps::serde::de::Deserialize<'de>,
-        {
-            fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+        impl<T> $name<Vec<T>> {
+            /// Creates a pointer to an empty `Vec` with room for `capacity` elements, returning an
+            /// error instead of aborting if the buffer can't be allocated. Only the buffer is
+            /// fallible; the pointer's own small allocation still aborts on failure, as
+            /// fallible `Rc`/`Arc` allocation is not available on stable.
+            pub fn try_with_capacity(
+                capacity: usize,
+            ) -> Result<Self, std::collections::TryReserveError> {
+                let mut buffer = Vec::new();
+                buffer.try_reserve_exact(capacity)?;
+                Ok($name::new(buffer))
+            }
+        }
+
+        impl $name<String> {
+            /// Like `SharedPtr::<Vec<T>>::try_with_capacity`, for a `String` of `capacity` bytes.
+            pub fn try_with_capacity(
+                capacity: usize,
+            ) -> Result<Self, std::collections::TryReserveError> {
+                let mut buffer = String::new();
+                buffer.try_reserve_exact(capacity)?;
+                Ok($name::new(buffer))
+            }
+        }
+
+        impl<T> $name<crate::padded::CachePadded<T>> {
+            /// Creates a pointer whose allocation is aligned and padded to a cache line, so that
+            /// pointers stored side by side (e.g. an array of counters) don't slow each other
+            /// down through false sharing. The value is reached through one extra deref.
+            pub fn new_padded(init: T) -> Self {
+                $name::new(crate::padded::CachePadded::new(init))
+            }
+        }
+
+        impl<T: ?Sized> $name<T> {
+            pub fn read(&self) -> $read_guard<'_, T> {
+                self.0.lock_read()
+            }
+
+            /// Returns the read guard if it can be acquired without blocking.
+            pub fn try_read(&self) -> Option<$read_guard<'_, T>> {
+                try_read_lock(&self.0.value, self.0.fairness)
+            }
+
+            #[track_caller]
+            pub fn write(&self) -> WriteGuard<'_, T> {
+                self.0.write(std::panic::Location::caller())
+            }
+
+            /// Read-locks the value and returns a guard onto the part of it selected by `f`.
+            pub fn read_field<V: ?Sized>(&self, f: impl FnOnce(&T) -> &V) -> FieldRef<'_, T, V> {
+                crate::field::FieldGuard::new(self.read()).map(f)
+            }
+
+            /// Write-locks the value and returns a guard onto the part of it selected by `f`.
+            /// Releasing it has the same effects as releasing a `write()` guard.
+            #[track_caller]
+            pub fn write_field<V: ?Sized>(
+                &self,
+                f: impl FnOnce(&mut T) -> &mut V,
+            ) -> FieldRefMut<'_, T, V> {
+                crate::field::FieldGuardMut::new(self.write()).map(f)
+            }
+
+            /// Applies `f` to a copy of the value under the write lock and only stores the copy if
+            /// `f` returns `Ok`. On error or panic the shared value is left untouched, so a
+            /// multi-step mutation is never observed half done. Either way the transaction is
+            /// released like a `write()` guard, so it counts as a write.
+            #[track_caller]
+            pub fn transaction<R, E>(&self, f: impl FnOnce(&mut T) -> Result<R, E>) -> Result<R, E>
             where
-                D: crate::deps::serde::Deserializer<'de>,
+                T: Clone,
             {
-                Ok($name::new(T::deserialize(deserializer)?))
+                let mut guard = self.write();
+                let mut shadow = T::clone(&guard);
+                let result = f(&mut shadow)?;
+                *guard = shadow;
+                Ok(result)
             }
-        }
 
-        #[cfg(feature = "serde")]
-        impl<T> crate::deps::serde::ser::Serialize for $name<T>
-        where
-            T: crate::deps::serde::ser::Serialize,
-        {
-            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            /// Computes an update from a snapshot of the value without holding any lock, then
+            /// takes the write lock only to `commit` it. If another write got in between, the
+            /// update is recomputed from a fresh snapshot. This keeps expensive derivations from
+            /// blocking other readers and writers.
+            #[track_caller]
+            pub fn optimistic_update<U, R>(
+                &self,
+                mut compute: impl FnMut(&T) -> U,
+                commit: impl FnOnce(&mut T, U) -> R,
+            ) -> R
             where
-                S: crate::deps::serde::Serializer,
+                T: Clone,
             {
-                let inner: $read_guard<'_, T> = self.0.deref().$read_fn();
-                inner.serialize(serializer)
+                let location = std::panic::Location::caller();
+                loop {
+                    let (version, snapshot) = {
+                        let guard = self.read();
+                        (self.version(), T::clone(&guard))
+                    };
+                    let update = compute(&snapshot);
+                    // Queued mutations would change the value under the update, so they are
+                    // applied as a write of their own and the update is recomputed.
+                    let guard = self.0.lock_write();
+                    if self.version() == version && !self.0.has_queued() {
+                        let pending = self.0.begin_write(location, &guard);
+                        let mut guard = WriteGuard {
+                            inner: &self.0,
+                            location,
+                            pending,
+                            guard: Some(guard),
+                        };
+                        return commit(&mut guard, update);
+                    }
+                    unlock_write(guard, self.0.fairness);
+                    if self.0.has_queued() {
+                        drop(self.0.write(location));
+                    }
+                }
             }
-        }
 
-        impl<T: ?Sized> Clone for $name<T> {
-            fn clone(&self) -> Self {
-                $name(self.0.clone())
+            /// Queues `mutation` without waiting for the lock. Queued mutations are applied in
+            /// order by the next `write()`, before it returns its guard, or by `flush()`; until
+            /// then readers don't see them. They count as part of that write for versions,
+            /// observers and audits.
+            ///
+            /// Only pointers created with `new_with_mailbox` defer; on others this is `write()`
+            /// followed by `mutation`.
+            #[track_caller]
+            pub fn queue_write(&self, mutation: impl FnOnce(&mut T) $(+ $send)? + 'static) {
+                match &self.0.mailbox {
+                    Some(mailbox) => mailbox.push(Box::new(mutation)),
+                    None => mutation(&mut self.write()),
+                }
+            }
+
+            /// Applies any mutations queued by `queue_write`, taking the write lock only if there
+            /// are some.
+            #[track_caller]
+            pub fn flush(&self) {
+                if self.0.has_queued() {
+                    drop(self.write());
+                }
+            }
+
+            /// Registers `finalizer` to run when the last strong pointer to the value is dropped,
+            /// just before the value itself. Use it to clean up resources tied to the shared
+            /// object, such as temporary files or registry entries. Finalizers run in reverse
+            /// order of registration, also when `try_unwrap` takes the value out. When `freeze` or
+            /// a conversion to another backend moves the value into a new handle, its finalizers
+            /// move along and run when that handle's value is dropped.
+            pub fn on_last_drop(&self, finalizer: impl FnOnce() $(+ $send)? + 'static) {
+                self.0.finalizers.push(Box::new(finalizer));
+            }
+
+            /// The policy the lock was created with. See `new_with_fairness`.
+            pub fn fairness(&self) -> crate::fairness::Fairness {
+                self.0.fairness
+            }
+
+            /// Counts the writes to the value so far. Every `write()` counts, whether or not it
+            /// changed anything.
+            pub fn version(&self) -> u64 {
+                self.0.version.load(std::sync::atomic::Ordering::Acquire)
+            }
+
+            /// Attaches a check that runs whenever a write guard is released, panicking with the
+            /// location of the offending `write()` if it fails. This catches corruption where it
+            /// happens rather than wherever it is noticed. The current value is checked
+            /// immediately.
+            ///
+            /// Checks only run in debug builds.
+            #[track_caller]
+            pub fn set_invariant(&self, check: fn(&T) -> Result<(), String>) {
+                *self.0.invariant.lock() = Some(check);
+                self.0.has_invariant.store(true, std::sync::atomic::Ordering::Release);
+                if cfg!(debug_assertions) {
+                    self.0.check_invariant(std::panic::Location::caller(), &self.read());
+                }
+            }
+
+            /// Pins the pointer to the current thread: any `write()` from another thread panics,
+            /// which enforces that a single thread owns the state.
+            ///
+            /// Checks only run in debug builds.
+            pub fn assert_writer_thread(&self) {
+                *self.0.writer_thread.lock() = Some(std::thread::current().id());
+                self.0.has_writer_thread.store(true, std::sync::atomic::Ordering::Release);
+            }
+
+            /// The name given to `new_named`.
+            pub fn name(&self) -> Option<&str> {
+                self.0.name.as_deref()
+            }
+
+            /// Returns how often the lock was acquired and contended, or `None` unless the
+            /// pointer was created with `new_with_stats`.
+            pub fn stats(&self) -> Option<crate::stats::LockStats> {
+                self.0.stats.as_ref().map(|stats| stats.snapshot())
+            }
+
+            /// Returns the recorded writes, oldest first. Empty unless the pointer was created
+            /// with `new_audited`.
+            pub fn audit(&self) -> Vec<crate::audit::AuditEntry> {
+                self.0.audit.as_ref().map(|audit| audit.entries()).unwrap_or_default()
+            }
+
+            /// Returns whether the value was written since the last call and clears the flag.
+            ///
+            /// Pointers created without tracking cannot know, so they always report dirty.
+            pub fn take_dirty(&self) -> bool {
+                match &self.0.dirty {
+                    Some(dirty) => dirty.swap(false, std::sync::atomic::Ordering::AcqRel),
+                    None => true,
+                }
+            }
+
+            /// Copies the value into a new, independent pointer. `clone()` only makes another
+            /// pointer to the same value.
+            pub fn deep_clone(&self) -> $name<T>
+            where
+                T: Clone,
+            {
+                $name::new(T::clone(&self.read()))
             }
         }
 
-        impl<T> std::cmp::PartialEq for $name<T>
+        impl<T> $name<T>
         where
-            T: Sized + PartialEq,
+            T: Clone + 'static $(+ $send)?,
         {
-            fn eq(&self, other: &Self) -> bool {
-                self.read().eq(&other.read())
+            /// Returns a stream yielding a snapshot of the value each time a write guard is
+            /// released.
+            pub fn changes(&self) -> crate::observe::Changes<T> {
+                self.subscribe(None)
             }
-        }
 
-        impl<T> std::cmp::Eq for $name<T> where T: Sized + Eq {}
+            /// Like `changes()`, but coalesces bursts of writes: a snapshot is only emitted once
+            /// no write guard has been released for `window`, and intermediate states are skipped.
+            pub fn changes_debounced(
+                &self,
+                window: std::time::Duration,
+            ) -> crate::observe::Changes<T> {
+                self.subscribe(Some(window))
+            }
 
-        impl<T> std::cmp::PartialOrd for $name<T>
-        where
-            T: Sized + PartialOrd,
-        {
-            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-                self.read().partial_cmp(&other.read())
+            fn subscribe(&self, window: Option<std::time::Duration>) -> crate::observe::Changes<T> {
+                let (publisher, changes) = crate::observe::channel(window);
+                self.0.observers.push(Box::new(move |value: &T| {
+                    let open = publisher.is_open();
+                    if open {
+                        publisher.send(value.clone());
+                    }
+                    open
+                }));
+                changes
             }
         }
 
-        impl<T> std::cmp::Ord for $name<T>
-        where
-            T: Sized + Ord,
-        {
-            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-                self.read().cmp(&other.read())
+        impl<T: ?Sized> $name<T> {
+            /// Starts keeping the last `capacity` states of the value, rendered with `Debug`,
+            /// beginning with the current one.
+            pub fn record_snapshots(&self, capacity: usize) -> crate::recorder::SnapshotRecorder
+            where
+                T: std::fmt::Debug,
+            {
+                let recorder = crate::recorder::SnapshotRecorder::new(capacity);
+                let sink = recorder.sink();
+                sink.record(&*self.read());
+                self.0.observers.push(Box::new(move |value: &T| sink.record(value)));
+                recorder
             }
         }
 
-        impl<T> std::hash::Hash for $name<T>
+        impl<T> $name<T>
         where
-            T: Sized + std::hash::Hash,
+            T: crate::observe::Diff + Clone + 'static $(+ $send)?,
+            T::Delta: 'static $(+ $send)?,
         {
-            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-                self.read().hash(state)
+            /// Returns a stream of what changed each time a write guard is released, skipping
+            /// writes that changed nothing. Unlike `changes()`, the value is cloned once up front
+            /// rather than on every write.
+            pub fn deltas(&self) -> crate::observe::Changes<T::Delta> {
+                let (publisher, deltas) = crate::observe::channel(None);
+                let shadow = crate::deps::parking_lot::Mutex::new(T::clone(&self.read()));
+                self.0.observers.push(Box::new(move |value: &T| {
+                    let open = publisher.is_open();
+                    if open {
+                        let mut shadow = shadow.lock();
+                        if let Some(delta) = value.diff(&shadow) {
+                            shadow.apply(&delta);
+                            publisher.send(delta);
+                        }
+                    }
+                    open
+                }));
+                deltas
             }
         }
 
-        impl<T> Default for $name<T>
-        where
-            T: Sized + Default,
-        {
-            fn default() -> Self {
-                $name::new(T::default())
+        impl<T> From<$guard<T>> for $name<T> {
+            fn from(value: $guard<T>) -> Self {
+                $name($ptr::new(Inner::new(value)))
             }
         }
 
-        #[derive(Debug)]
-        pub struct $weak_name<T: ?Sized>($weak_ptr<$guard<T>>);
+        impl<T> $name<T> {
+            /// Takes over the value of a bare `Rc` or `Arc` around the backend's lock, if it is
+            /// the only strong reference to it. Otherwise it is returned unchanged, since its
+            /// other owners would no longer see writes through the new pointer.
+            pub fn try_from_ptr(ptr: $ptr<$guard<T>>) -> Result<Self, $ptr<$guard<T>>> {
+                $ptr::try_unwrap(ptr).map($name::from)
+            }
+        }
 
+        /// See `try_from_ptr`.
+        impl<T> std::convert::TryFrom<$ptr<$guard<T>>> for $name<T> {
+            type Error = $ptr<$guard<T>>;
 
-        impl<T: ?Sized> $weak_name<T> {
-            pub fn downgrade(strong: &$name<T>) -> Self {
-                $weak_name($ptr::downgrade(&strong.0))
+            fn try_from(ptr: $ptr<$guard<T>>) -> Result<Self, Self::Error> {
+                $name::try_from_ptr(ptr)
             }
+        }
 
-            pub fn upgrade(&self) -> Option<$name<T>> {
-                self.0.upgrade().map($name)
+        impl<T> From<T> for $name<T> {
+            fn from(value: T) -> Self {
+                $name::new(value)
             }
         }
 
+        impl<T> From<Box<T>> for $name<T> {
+            fn from(value: Box<T>) -> Self {
+                $name::new(*value)
+            }
+        }
 
-        impl<T> $weak_name<T>
+        impl<C, A> std::iter::FromIterator<A> for $name<C>
         where
-            T: Sized,
+            C: std::iter::FromIterator<A>,
         {
-            pub fn new() -> $weak_name<T> {
-                $weak_name(<$weak_ptr<$guard<T>>>::new())
+            fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+                $name::new(C::from_iter(iter))
             }
         }
 
-        #[test]
-        fn test_interior_mutability() {
-            use std::collections::HashMap;
-            let mut map = HashMap::<usize, $name<u32>>::new();
+        /// Takes the write lock once for the whole iterator.
+        impl<C, A> Extend<A> for $name<C>
+        where
+            C: Extend<A>,
+        {
+            #[track_caller]
+            fn extend<I: IntoIterator<Item = A>>(&mut self, iter: I) {
+                self.write().extend(iter)
+            }
+        }
 
-            let answer = $name::new(0u32);
-            for i in 1..=1024usize {
-                assert!(map.insert(i, answer.clone()).is_none());
+        impl<T: ?Sized> $name<T> {
+            /// The address of the value, which stays the same for the lifetime of the allocation
+            /// and is shared by all clones. Dereferencing it is only sound while a guard is held.
+            pub fn data_ptr(&self) -> *mut T {
+                data_ptr(&self.0.value)
             }
 
-            *(answer.write()) = 42u32;
-            assert!(map.values().all(|v| *(v.read()) == 42u32))
-        }
-    };
-}
+            /// A copyable token identifying the allocation, for correlating log lines or keying
+            /// maps without holding the pointer.
+            pub fn id(&self) -> crate::by_address::SharedPtrId {
+                use crate::by_address::Address;
+                crate::by_address::SharedPtrId::new(self.address(), self.0.generation)
+            }
 
-pub mod rc_refcell {
-    use core::cell::{
-        Ref,
-        RefCell,
-        RefMut,
-    };
-    use std::rc::Weak;
-    use std::{
-        ops::Deref,
-        rc::Rc,
-    };
+            /// Consumes the pointer without releasing its strong reference and returns an opaque
+            /// pointer to the allocation, e.g. to pass through a C callback's `void *` context.
+            /// Convert it back with `from_raw`, or the value is leaked. The pointer keeps the
+            /// provenance of the allocation, so it may be cast but not round-tripped through an
+            /// integer.
+            pub fn into_raw(self) -> *const () {
+                $ptr::into_raw(self.0).cast::<()>()
+            }
 
-    use crate::deps::owning_ref::RefRef;
+            /// Leaks the pointer, for values that live as long as the process. The allocation is
+            /// never freed, so the returned reference can be stored in a `static`.
+            pub fn leak(self) -> &'static Self
+            where
+                T: 'static,
+            {
+                Box::leak(Box::new(self))
+            }
+        }
 
-    pub type FieldRef<'a, T, V> = RefRef<'a, T, V>;
+        impl<T: ?Sized> crate::by_address::Address for $name<T> {
+            fn address(&self) -> *const () {
+                $ptr::as_ptr(&self.0) as *const ()
+            }
+        }
 
-    define_shared_mut!(SharedPtr, WeakPtr, Rc, Weak, RefCell, borrow, borrow_mut, Ref, RefMut);
-}
+        impl<'a, T: ?Sized> crate::stm::Lockable<'a> for &'a $name<T> {
+            type Guard = WriteGuard<'a, T>;
 
-pub mod arc_mutex {
-    use crate::deps::owning_ref::OwningRef;
+            fn address(&self) -> *const () {
+                crate::by_address::Address::address(*self)
+            }
 
-    use std::ops::Deref;
-    use std::sync::{
-        Arc,
-        Weak,
-    };
+            fn lock(self) -> WriteGuard<'a, T> {
+                self.write()
+            }
+        }
 
-    use crate::deps::parking_lot::{
-        Mutex,
-        MutexGuard,
-    };
+        /// Exclusive access to the value. Observers are notified when the guard is dropped.
+        pub struct WriteGuard<'a, T: ?Sized> {
+            inner: &'a Inner<T>,
+            location: &'static std::panic::Location<'static>,
+            pending: Option<crate::audit::PendingAudit>,
+            // Only `None` once the guard has been released in `drop`.
+            guard: Option<$write_guard<'a, T>>,
+        }
 
-    pub type FieldRef<'a, T, V> = OwningRef<MutexGuard<'a, T>, V>;
+        impl<'a, T: ?Sized> WriteGuard<'a, T> {
+            /// Splits the guard into guards onto two disjoint parts of the value, like
+            /// `RefMut::map_split`, so each can be handed to different code. The lock is released,
+            /// with the usual effects of releasing a write guard, once both are dropped.
+            ///
+            /// This is an associated function so it can't be shadowed by a method of `T`.
+            pub fn map_split<A: ?Sized, B: ?Sized>(
+                guard: Self,
+                f: impl FnOnce(&mut T) -> (&mut A, &mut B),
+            ) -> (SplitRefMut<'a, T, A>, SplitRefMut<'a, T, B>) {
+                crate::field::SplitGuardMut::split(guard, f)
+            }
+        }
 
-    define_shared_mut!(SharedPtr, WeakPtr, Arc, Weak, Mutex, lock, lock, MutexGuard, MutexGuard);
-}
+        impl<T: ?Sized> std::ops::Deref for WriteGuard<'_, T> {
+            type Target = T;
 
-pub mod arc_rwlock {
-    use crate::deps::owning_ref::OwningRef;
+            fn deref(&self) -> &T {
+                self.guard.as_deref().unwrap()
+            }
+        }
 
-    use std::ops::Deref;
-    use std::sync::{
-        Arc,
-        Weak,
-    };
+        impl<T: ?Sized> std::ops::DerefMut for WriteGuard<'_, T> {
+            fn deref_mut(&mut self) -> &mut T {
+                self.guard.as_deref_mut().unwrap()
+            }
+        }
+
+        // SAFETY: derefs into the backend lock's heap allocation.
+        unsafe impl<T: ?Sized> crate::field::StableGuard for WriteGuard<'_, T> {}
+
+        impl<T: ?Sized> Drop for WriteGuard<'_, T> {
+            fn drop(&mut self) {
+                if let Some(guard) = &self.guard {
+                    self.inner.end_write(self.location, self.pending.take(), guard);
+                }
+                if let Some(guard) = self.guard.take() {
+                    unlock_write(guard, self.inner.fairness);
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for WriteGuard<'_, T>
+        where
+            T: ?Sized + std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                std::fmt::Debug::fmt(&**self, f)
+            }
+        }
+
+        impl<T> std::fmt::Debug for $name<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match self.name() {
+                    None => f.debug_tuple(stringify!($name)).field(&self.read()).finish(),
+                    Some(name) => f
+                        .debug_struct(stringify!($name))
+                        .field("name", &name)
+                        .field("value", &self.read())
+                        .finish(),
+                }
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de, T> crate::deps::serde::de::Deserialize<'de> for $name<T>
+        where
+            T: Sized + crate::deps::serde::de::Deserialize<'de>,
+        {
+            fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+            where
+                D: crate::deps::serde::Deserializer<'de>,
+            {
+                Ok($name::new(T::deserialize(deserializer)?))
+            }
+        }
+
+        /// Inside `sharing::PreserveSharing`, deserializes from an `(id, Option<T>)` pair,
+        /// which is why `T` must be `'static`. Elsewhere the same as a bare pointer.
+        #[cfg(feature = "serde")]
+        impl<'de, T> crate::deps::serde::de::Deserialize<'de> for crate::sharing::Shared<$name<T>>
+        where
+            T: Sized + crate::deps::serde::de::Deserialize<'de> + 'static,
+        {
+            fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+            where
+                D: crate::deps::serde::Deserializer<'de>,
+            {
+                if !crate::sharing::is_deserializing() {
+                    return $name::deserialize(deserializer).map(crate::sharing::Shared);
+                }
+
+                match <(u64, Option<T>)>::deserialize(deserializer)? {
+                    (id, Some(value)) => {
+                        let ptr = $name::new(value);
+                        crate::sharing::insert(id, ptr.clone());
+                        Ok(crate::sharing::Shared(ptr))
+                    }
+                    (id, None) => {
+                        let ptr = crate::sharing::lookup(id).ok_or_else(|| {
+                            <D::Error as crate::deps::serde::de::Error>::custom(format_args!(
+                                "shared pointer id {} does not refer to an earlier {}",
+                                id,
+                                std::any::type_name::<$name<T>>()
+                            ))
+                        })?;
+                        Ok(crate::sharing::Shared(ptr))
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<T> $name<T> {
+            /// Deserializes a new value and stores it in this pointer under the write lock, so
+            /// every existing clone sees it. On error the current value is left untouched.
+            #[track_caller]
+            pub fn deserialize_into<'de, D>(&self, deserializer: D) -> Result<(), D::Error>
+            where
+                T: crate::deps::serde::de::Deserialize<'de>,
+                D: crate::deps::serde::Deserializer<'de>,
+            {
+                let value = T::deserialize(deserializer)?;
+                *self.write() = value;
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<T: ?Sized> $name<T> {
+            /// Read-locks the value to serialize it, or returns `None` if inside a
+            /// `TrySerialize` and someone else holds the lock.
+            fn serialize_guard(&self) -> Option<$read_guard<'_, T>> {
+                match crate::try_serialize::on_contention() {
+                    None => Some(self.0.lock_read()),
+                    Some(_) => self.try_read(),
+                }
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<T> crate::deps::serde::ser::Serialize for $name<T>
+        where
+            T: ?Sized + crate::deps::serde::ser::Serialize,
+        {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: crate::deps::serde::Serializer,
+            {
+                match self.serialize_guard() {
+                    Some(inner) => inner.serialize(serializer),
+                    None => {
+                        let pointer = std::any::type_name::<Self>();
+                        crate::try_serialize::contended(serializer, pointer)
+                    }
+                }
+            }
+        }
+
+        /// Inside `sharing::PreserveSharing`, serializes as an `(id, Option<T>)` pair, with the
+        /// value only in the first pair for each pointer. Elsewhere the same as a bare pointer.
+        #[cfg(feature = "serde")]
+        impl<T> crate::deps::serde::ser::Serialize for crate::sharing::Shared<$name<T>>
+        where
+            T: ?Sized + crate::deps::serde::ser::Serialize,
+        {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: crate::deps::serde::Serializer,
+            {
+                let address = $ptr::as_ptr(&(self.0).0) as *const ();
+                match crate::sharing::serialized_id(address) {
+                    None => return self.0.serialize(serializer),
+                    Some(Some(id)) => return (id, None::<&T>).serialize(serializer),
+                    Some(None) => {}
+                }
+                // The id is only taken once the value can be written out, so that a contended
+                // pointer's placeholder isn't mistaken for its first occurrence.
+                match self.0.serialize_guard() {
+                    Some(inner) => {
+                        let id = crate::sharing::register(address);
+                        (id, Some(&*inner)).serialize(serializer)
+                    }
+                    None => {
+                        let pointer = std::any::type_name::<$name<T>>();
+                        crate::try_serialize::contended(serializer, pointer)
+                    }
+                }
+            }
+        }
+
+        /// Returns another pointer to the same value. See `deep_clone` for a copy.
+        impl<T: ?Sized> Clone for $name<T> {
+            fn clone(&self) -> Self {
+                $name(self.0.clone())
+            }
+        }
+
+        impl<T: ?Sized> $name<T> {
+            /// Read-locks both values in address order, so that two threads comparing the same
+            /// pair of pointers in opposite directions can't deadlock. Two pointers to the same
+            /// value, which a mutex would deadlock on, lock it once and pass it twice.
+            fn read_both<R>(&self, other: &Self, f: impl FnOnce(&T, &T) -> R) -> R {
+                use crate::by_address::Address;
+                if $ptr::ptr_eq(&self.0, &other.0) {
+                    let this = self.read();
+                    f(&this, &this)
+                } else if self.address() < other.address() {
+                    let this = self.read();
+                    f(&this, &other.read())
+                } else {
+                    let that = other.read();
+                    f(&self.read(), &that)
+                }
+            }
+        }
+
+        /// Both values are locked in address order and compared. A pointer is compared with
+        /// itself under a single lock, so a value that isn't equal to itself, like `NaN`, isn't
+        /// equal through the pointer either.
+        impl<T> std::cmp::PartialEq for $name<T>
+        where
+            T: Sized + PartialEq,
+        {
+            fn eq(&self, other: &Self) -> bool {
+                self.read_both(other, T::eq)
+            }
+        }
+
+        impl<T> std::cmp::Eq for $name<T> where T: Sized + Eq {}
+
+        /// Compares the value against a plain one, e.g. `assert_eq!(ptr, 3)`.
+        impl<T> std::cmp::PartialEq<T> for $name<T>
+        where
+            T: Sized + PartialEq,
+        {
+            fn eq(&self, other: &T) -> bool {
+                *self.read() == *other
+            }
+        }
+
+        impl<T> std::cmp::PartialEq<&T> for $name<T>
+        where
+            T: Sized + PartialEq,
+        {
+            fn eq(&self, other: &&T) -> bool {
+                *self.read() == **other
+            }
+        }
+
+        impl<T> std::cmp::PartialOrd for $name<T>
+        where
+            T: Sized + PartialOrd,
+        {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                self.read_both(other, T::partial_cmp)
+            }
+        }
+
+        impl<T> std::cmp::Ord for $name<T>
+        where
+            T: Sized + Ord,
+        {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                if $ptr::ptr_eq(&self.0, &other.0) {
+                    return std::cmp::Ordering::Equal;
+                }
+                self.read_both(other, T::cmp)
+            }
+        }
+
+        impl<T> std::hash::Hash for $name<T>
+        where
+            T: Sized + std::hash::Hash,
+        {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.read().hash(state)
+            }
+        }
+
+        impl<T> Default for $name<T>
+        where
+            T: Sized + Default,
+        {
+            fn default() -> Self {
+                $name::new(T::default())
+            }
+        }
+
+        /// A weak reference to a pointer's value. The reference sits behind a lock so that
+        /// `upgrade_or_init` can repoint it through a shared reference.
+        #[derive(Debug)]
+        pub struct $weak_name<T: ?Sized>(crate::deps::parking_lot::Mutex<$weak_ptr<Inner<T>>>);
+
+
+        impl<T: ?Sized> $weak_name<T> {
+            pub fn downgrade(strong: &$name<T>) -> Self {
+                $weak_name(crate::deps::parking_lot::Mutex::new($ptr::downgrade(&strong.0)))
+            }
+
+            pub fn upgrade(&self) -> Option<$name<T>> {
+                self.0.lock().upgrade().map($name)
+            }
+
+            /// The number of strong pointers to the value, 0 once it has been dropped.
+            pub fn strong_count(&self) -> usize {
+                self.0.lock().strong_count()
+            }
+        }
+
+        impl<T: ?Sized> crate::collections::Downgrade for $name<T> {
+            type Weak = $weak_name<T>;
+
+            fn downgrade(&self) -> $weak_name<T> {
+                $weak_name::downgrade(self)
+            }
+
+            fn upgrade(weak: &$weak_name<T>) -> Option<Self> {
+                weak.upgrade()
+            }
+        }
+
+        impl<T: ?Sized> Clone for $weak_name<T> {
+            fn clone(&self) -> Self {
+                $weak_name(crate::deps::parking_lot::Mutex::new(self.0.lock().clone()))
+            }
+        }
+
+        impl<T: ?Sized> crate::by_address::Address for $weak_name<T> {
+            fn address(&self) -> *const () {
+                self.0.lock().as_ptr() as *const ()
+            }
+        }
+
+
+        impl<T> $weak_name<T>
+        where
+            T: Sized,
+        {
+            pub fn new() -> $weak_name<T> {
+                $weak_name(crate::deps::parking_lot::Mutex::new(<$weak_ptr<Inner<T>>>::new()))
+            }
+
+            /// Upgrades the pointer, or if the value has been dropped creates a new one with
+            /// `init` and points this weak pointer at it. Concurrent callers are serialized, so
+            /// they all get the same new value; `init` runs with this weak pointer locked and
+            /// must not use it.
+            pub fn upgrade_or_init(&self, init: impl FnOnce() -> T) -> $name<T> {
+                let mut weak = self.0.lock();
+                if let Some(strong) = weak.upgrade() {
+                    return $name(strong);
+                }
+                let strong = $name::new(init());
+                *weak = $ptr::downgrade(&strong.0);
+                strong
+            }
+        }
+
+        #[test]
+        fn test_interior_mutability() {
+            use std::collections::HashMap;
+            let mut map = HashMap::<usize, $name<u32>>::new();
+
+            let answer = $name::new(0u32);
+            for i in 1..=1024usize {
+                assert!(map.insert(i, answer.clone()).is_none());
+            }
+
+            *(answer.write()) = 42u32;
+            assert!(map.values().all(|v| *(v.read()) == 42u32))
+        }
+
+        #[test]
+        fn test_take_dirty() {
+            let tracked = $name::new_tracked(0u32);
+            assert!(!tracked.take_dirty());
+
+            *(tracked.clone().write()) += 1;
+            assert!(tracked.take_dirty());
+            assert!(!tracked.take_dirty());
+
+            assert_eq!(*(tracked.read()), 1u32);
+            assert!(!tracked.take_dirty());
+
+            let untracked = $name::new(0u32);
+            assert!(untracked.take_dirty());
+        }
+
+        #[test]
+        fn test_transaction_rollback() {
+            let ptr = $name::new(vec![1u32]);
+
+            let result: Result<(), &str> = ptr.transaction(|v| {
+                v.push(2);
+                Err("abort")
+            });
+            assert_eq!(result, Err("abort"));
+            assert_eq!(*(ptr.read()), vec![1]);
+            assert_eq!(ptr.version(), 1);
+
+            let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                ptr.transaction(|v| -> Result<(), ()> {
+                    v.push(2);
+                    panic!("boom")
+                })
+            }));
+            assert!(panicked.is_err());
+            assert_eq!(*(ptr.read()), vec![1]);
+            assert_eq!(ptr.version(), 2);
+
+            assert_eq!(ptr.transaction(|v| Ok::<_, ()>(v.push(2))), Ok(()));
+            assert_eq!(*(ptr.read()), vec![1, 2]);
+        }
+
+        #[test]
+        fn test_audit() {
+            let ptr = $name::new_audited(1u32, 2, Some(|v: &u32| v.to_string()));
+            *(ptr.write()) += 1;
+            *(ptr.write()) += 1;
+            *(ptr.write()) += 1;
+
+            let entries = ptr.audit();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].location.file(), file!());
+            assert_eq!(entries[1].before.as_deref(), Some("3"));
+            assert_eq!(entries[1].after.as_deref(), Some("4"));
+
+            assert!($name::new(0u32).audit().is_empty());
+        }
+
+        #[test]
+        #[cfg(debug_assertions)]
+        fn test_invariant() {
+            let ptr = $name::new(vec![1u32, 2]);
+            ptr.set_invariant(|v| match v.windows(2).all(|w| w[0] <= w[1]) {
+                true => Ok(()),
+                false => Err(format!("{:?} is not sorted", v)),
+            });
+            ptr.write().push(3);
+
+            let violated = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                ptr.write().push(0);
+            }));
+            let message = *violated.unwrap_err().downcast::<String>().unwrap();
+            assert!(message.contains("[1, 2, 3, 0] is not sorted"), "{}", message);
+            assert!(message.contains(file!()), "{}", message);
+        }
+
+        #[test]
+        #[cfg(feature = "serde")]
+        fn test_deserialize_into() {
+            use crate::test_value::Value;
+
+            let ptr = $name::new(1u32);
+            let held = ptr.clone();
+            ptr.deserialize_into(Value::U64(2)).unwrap();
+            assert_eq!(*(held.read()), 2);
+
+            assert!(ptr.deserialize_into(Value::Str("nope".into())).is_err());
+            assert_eq!(*(held.read()), 2);
+        }
+
+        #[test]
+        #[cfg(feature = "serde")]
+        fn test_serialize_boxed_trait_object() {
+            use crate::deps::serde::{
+                Serialize,
+                Serializer,
+            };
+            use crate::test_value::{
+                to_value,
+                Value,
+            };
+
+            trait Plugin {
+                fn state(&self) -> u32;
+            }
+
+            struct Counter(u32);
+
+            impl Plugin for Counter {
+                fn state(&self) -> u32 {
+                    self.0
+                }
+            }
+
+            // What `erased_serde::serialize_trait_object!` generates, minus the erasure.
+            impl Serialize for dyn Plugin {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    self.state().serialize(serializer)
+                }
+            }
+
+            let plugin: $name<Box<dyn Plugin>> = $name::new(Box::new(Counter(7)));
+            assert_eq!(to_value(&plugin).unwrap(), Value::U64(7));
+        }
+
+        #[test]
+        fn test_field_refs() {
+            let ptr = $name::new((String::from("a"), vec![1u32, 2]));
+            let mut changes = ptr.changes();
+
+            ptr.write_field(|v| &mut v.1).push(3);
+            assert_eq!(changes.try_next().map(|v| v.1), Some(vec![1, 2, 3]));
+
+            let field: FieldRef<'_, _, [u32]> = ptr.read_field(|v| &v.1[..]);
+            assert_eq!(&*field.map(|v| &v[1..]), &[2, 3]);
+
+            let mut name: FieldRefMut<'_, _, str> =
+                ptr.write_field(|v| &mut v.0).map(|s| s.as_mut_str());
+            name.make_ascii_uppercase();
+            drop(name);
+            assert_eq!(*(ptr.read_field(|v| &v.0)), "A");
+
+            let count = FieldRef::new(ptr.read()).map(|v| &v.1).len();
+            assert_eq!(count, 3);
+            FieldRefMut::new(ptr.write()).map(|v| &mut v.0).push('b');
+            assert_eq!(ptr.read().0, "Ab");
+        }
+
+        #[test]
+        fn test_deep_clone() {
+            let original = $name::new(vec![1u32]);
+            let alias = original.clone();
+            let copy = original.deep_clone();
+            original.write().push(2);
+            assert_eq!(*alias.read(), [1, 2]);
+            assert_eq!(*copy.read(), [1]);
+        }
+
+        #[test]
+        fn test_optimistic_update() {
+            let ptr = $name::new(vec![3u32, 1, 2]);
+            assert_eq!(ptr.version(), 0);
+
+            let mut attempts = 0;
+            let sorted = ptr.optimistic_update(
+                |v| {
+                    attempts += 1;
+                    if attempts == 1 {
+                        // Another writer gets in while the first attempt computes.
+                        ptr.write().push(0);
+                    }
+                    let mut sorted = v.clone();
+                    sorted.sort();
+                    sorted
+                },
+                |v, sorted| {
+                    *v = sorted;
+                    v.len()
+                },
+            );
+            assert_eq!(attempts, 2);
+            assert_eq!(sorted, 4);
+            assert_eq!(*ptr.read(), [0, 1, 2, 3]);
+            assert_eq!(ptr.version(), 2);
+        }
+
+        #[test]
+        fn test_queue_write() {
+            let ptr = $name::new_with_mailbox(vec![1u32]);
+            ptr.queue_write(|v| v.push(2));
+            ptr.queue_write(|v| v.push(3));
+            assert_eq!(*ptr.read(), [1]);
+
+            ptr.write().push(4);
+            assert_eq!(*ptr.read(), [1, 2, 3, 4]);
+            assert_eq!(ptr.version(), 1);
+
+            ptr.queue_write(|v| v.clear());
+            ptr.flush();
+            assert!(ptr.read().is_empty());
+            ptr.flush();
+            assert_eq!(ptr.version(), 2);
+
+            ptr.queue_write(|v| v.push(1));
+            let pushed = ptr.transaction(|v| {
+                v.push(2);
+                Ok::<_, ()>(v.len())
+            });
+            assert_eq!((pushed, ptr.version()), (Ok(2), 3));
+            ptr.queue_write(|v| v.push(3));
+            let len = ptr.optimistic_update(Vec::len, |v, len| {
+                v.push(4);
+                len
+            });
+            assert_eq!((len, ptr.version()), (3, 5));
+            assert_eq!(*ptr.read(), [1, 2, 3, 4]);
+
+            ptr.queue_write(|v| v.push(5));
+            assert_eq!(ptr.try_unwrap().unwrap(), [1, 2, 3, 4, 5]);
+        }
+
+        #[test]
+        fn test_on_last_drop() {
+            let dropped = std::sync::Arc::new(crate::deps::parking_lot::Mutex::new(Vec::new()));
+            let ptr = $name::new(0u32);
+            for i in 0..2 {
+                let dropped = dropped.clone();
+                ptr.on_last_drop(move || dropped.lock().push(i));
+            }
+            let alias = ptr.clone();
+            drop(ptr);
+            assert!(dropped.lock().is_empty());
+            drop(alias);
+            assert_eq!(*dropped.lock(), [1, 0]);
+        }
+
+        #[test]
+        fn test_compare_without_deadlock() {
+            let ptr = $name::new(vec![1u32]);
+            let alias = ptr.clone();
+            assert!(ptr == alias);
+            assert_eq!(ptr.cmp(&alias), std::cmp::Ordering::Equal);
+
+            let other = $name::new(vec![2u32]);
+            assert!(ptr < other);
+            assert!(other > ptr);
+            assert!(other != ptr);
+
+            let nan = $name::new(f64::NAN);
+            let alias = nan.clone();
+            assert!(nan != alias);
+            assert_eq!(nan.partial_cmp(&alias), None);
+        }
+
+        #[test]
+        fn test_data_ptr_and_leak() {
+            let ptr = $name::new(5u32);
+            let address = ptr.data_ptr();
+            assert_eq!(address, ptr.clone().data_ptr());
+            assert!(std::ptr::eq(address, &*ptr.read()));
+
+            let leaked: &'static $name<u32> = ptr.leak();
+            *leaked.write() += 1;
+            assert_eq!(*leaked.read(), 6);
+        }
+
+        #[test]
+        fn test_builder() {
+            let finalized = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let ptr = $name::builder(1u32)
+                .name("counter")
+                .fair()
+                .with_stats()
+                .tracked()
+                .audited(4)
+                .padded()
+                .on_last_drop({
+                    let finalized = finalized.clone();
+                    move || finalized.store(true, std::sync::atomic::Ordering::SeqCst)
+                })
+                .build();
+            **ptr.write() += 1;
+            assert_eq!(**ptr.read(), 2);
+            assert_eq!(ptr.name(), Some("counter"));
+            assert_eq!(ptr.fairness(), crate::fairness::Fairness::Fair);
+            assert_eq!(ptr.stats().unwrap().writes, 1);
+            assert!(ptr.take_dirty());
+            assert_eq!(ptr.audit().len(), 1);
+            drop(ptr);
+            assert!(finalized.load(std::sync::atomic::Ordering::SeqCst));
+        }
+
+        #[test]
+        fn test_map_split() {
+            let ptr = $name::new((vec![1u32], String::from("a")));
+            let (mut numbers, mut text) =
+                WriteGuard::map_split(ptr.write(), |(numbers, text)| (numbers, text));
+            numbers.push(2);
+            text.push('b');
+            drop(numbers);
+            assert!(ptr.try_read().is_none());
+            drop(text);
+            assert_eq!(*ptr.read(), (vec![1, 2], String::from("ab")));
+            assert_eq!(ptr.version(), 1);
+        }
+
+        #[test]
+        fn test_deep_clone_boxed_trait_object() {
+            trait Plugin {
+                fn bump(&mut self) -> u32;
+
+                fn clone_box(&self) -> Box<dyn Plugin>;
+            }
+
+            #[derive(Clone)]
+            struct Counter(u32);
+
+            impl Plugin for Counter {
+                fn bump(&mut self) -> u32 {
+                    self.0 += 1;
+                    self.0
+                }
+
+                fn clone_box(&self) -> Box<dyn Plugin> {
+                    Box::new(self.clone())
+                }
+            }
+
+            // What `dyn_clone::clone_trait_object!` generates.
+            impl Clone for Box<dyn Plugin> {
+                fn clone(&self) -> Self {
+                    self.clone_box()
+                }
+            }
+
+            let plugin: $name<Box<dyn Plugin>> = $name::new(Box::new(Counter(0)));
+            let copy = plugin.deep_clone();
+            assert_eq!(plugin.write().bump(), 1);
+            assert_eq!(copy.write().bump(), 1);
+
+            let rolled_back: Result<(), ()> = plugin.transaction(|plugin| {
+                plugin.bump();
+                Err(())
+            });
+            assert!(rolled_back.is_err());
+            assert_eq!(plugin.write().bump(), 2);
+        }
+
+        #[test]
+        fn test_into_raw_round_trip() {
+            let ptr = $name::new(vec![1u32]);
+            let alias = ptr.clone();
+            let data = ptr.data_ptr();
+
+            let raw = ptr.into_raw();
+            // SAFETY: `raw` came from `into_raw` on a `$name<Vec<u32>>` and is used once.
+            let ptr = unsafe { $name::<Vec<u32>>::from_raw(raw) };
+            ptr.write().push(2);
+            assert_eq!(ptr.data_ptr(), data);
+            assert_eq!(*alias.read(), [1, 2]);
+
+            drop(ptr);
+            assert_eq!(alias.try_unwrap().unwrap(), [1, 2]);
+        }
+
+        #[test]
+        fn test_new_with_weak() {
+            let (ptr, weak) = $name::new_with_weak(1u32);
+            *weak.upgrade().unwrap().write() += 1;
+            assert_eq!(*ptr.read(), 2);
+            drop(ptr);
+            assert!(weak.upgrade().is_none());
+        }
+
+        #[test]
+        fn test_eq_plain_value() {
+            let ptr = $name::new(String::from("a"));
+            assert_eq!(ptr, String::from("a"));
+            assert_eq!(ptr, &String::from("a"));
+            assert_ne!(ptr, String::from("b"));
+        }
+
+        #[test]
+        fn test_try_with_capacity() {
+            let buffer = $name::<Vec<u64>>::try_with_capacity(1024).unwrap();
+            assert!(buffer.read().capacity() >= 1024);
+            assert!($name::<Vec<u64>>::try_with_capacity(usize::MAX).is_err());
+            assert!($name::<String>::try_with_capacity(isize::MAX as usize).is_err());
+        }
+
+        #[test]
+        fn test_upgrade_or_init() {
+            use crate::by_address::Address;
+
+            let weak = $weak_name::new();
+            let first = weak.upgrade_or_init(|| 1u32);
+            assert_eq!(weak.upgrade_or_init(|| 2).address(), first.address());
+            drop(first);
+            assert_eq!(*weak.upgrade_or_init(|| 3).read(), 3);
+        }
+
+        #[test]
+        fn test_from_ptr() {
+            let bare = $ptr::new($guard::new(vec![1u32]));
+            let other = bare.clone();
+            let bare = $name::try_from_ptr(bare).unwrap_err();
+            drop(other);
+            let ptr: $name<Vec<u32>> = std::convert::TryInto::try_into(bare).unwrap();
+            ptr.write().push(2);
+            assert_eq!(*ptr.read(), [1, 2]);
+        }
+
+        #[test]
+        fn test_named() {
+            let scene = $name::new_named("render.scene", 1u32);
+            assert_eq!(scene.name(), Some("render.scene"));
+            assert_eq!($name::new(1u32).name(), None);
+            assert_eq!(
+                format!("{:?}", scene),
+                concat!(stringify!($name), " { name: \"render.scene\", value: 1 }")
+            );
+            assert_eq!(format!("{:?}", $name::new(1u32)), concat!(stringify!($name), "(1)"));
+        }
+
+        #[test]
+        fn test_from() {
+            let from_value: $name<u32> = 1.into();
+            let from_box: $name<u32> = Box::new(2).into();
+            let from_lock: $name<u32> = $name::from($guard::new(3));
+            assert_eq!((*from_value.read(), *from_box.read(), *from_lock.read()), (1, 2, 3));
+        }
+
+        #[test]
+        fn test_collect_and_extend() {
+            let mut evens: $name<Vec<u32>> = (0..3).map(|i| i * 2).collect();
+            let alias = evens.clone();
+            evens.extend(vec![6, 8]);
+            assert_eq!(*alias.read(), [0, 2, 4, 6, 8]);
+        }
+
+        #[test]
+        fn test_changes() {
+            use std::task::{
+                Context,
+                Poll,
+                Waker,
+            };
+
+            let ptr = $name::new(0u32);
+            let mut changes = ptr.changes();
+            assert_eq!(changes.try_next(), None);
+
+            *(ptr.write()) = 1;
+            *(ptr.clone().write()) = 2;
+            assert_eq!(changes.try_next(), Some(1));
+            assert_eq!(changes.try_next(), Some(2));
+
+            let mut cx = Context::from_waker(Waker::noop());
+            assert_eq!(changes.poll_next(&mut cx), Poll::Pending);
+            drop(ptr);
+            assert_eq!(changes.poll_next(&mut cx), Poll::Ready(None));
+        }
+
+        #[test]
+        fn test_deltas() {
+            use crate::observe::MapDelta;
+            use std::collections::BTreeMap;
+
+            let ptr = $name::new((0..10_000u32).map(|i| (i, i)).collect::<BTreeMap<_, _>>());
+            let mut deltas = ptr.deltas();
+
+            {
+                let mut map = ptr.write();
+                map.insert(1, 100);
+                map.remove(&2);
+            }
+            ptr.write().insert(3, 3);
+
+            assert_eq!(
+                deltas.try_next(),
+                Some(MapDelta {
+                    upserted: vec![(1, 100)],
+                    removed: vec![2],
+                })
+            );
+            assert_eq!(deltas.try_next(), None);
+        }
+
+        #[test]
+        fn test_changes_debounced() {
+            use std::time::Duration;
+
+            let ptr = $name::new(0u32);
+            let mut changes = ptr.changes_debounced(Duration::from_millis(20));
+            for i in 1..=3 {
+                *(ptr.write()) = i;
+            }
+            assert_eq!(changes.try_next(), None);
+
+            std::thread::sleep(Duration::from_millis(40));
+            assert_eq!(changes.try_next(), Some(3));
+            assert_eq!(changes.try_next(), None);
+        }
+    };
+}
+
+#[cfg(feature = "rc")]
+pub mod rc_refcell {
+    use core::cell::{
+        Ref,
+        RefCell,
+        RefMut,
+    };
+    use std::rc::{
+        Rc,
+        Weak,
+    };
+
+    use crate::fairness::Fairness;
+    use crate::registry::{
+        LockState,
+        Probe,
+        Registration,
+    };
+
+    pub type FieldRef<'a, T, V> = crate::field::FieldGuard<Ref<'a, T>, V>;
+
+    pub type FieldRefMut<'a, T, V> = crate::field::FieldGuardMut<WriteGuard<'a, T>, V>;
+
+    pub type SplitRefMut<'a, T, V> = crate::field::SplitGuardMut<WriteGuard<'a, T>, V>;
+
+    type Observer<T> = dyn Fn(&T) -> bool;
+
+    fn read_lock<T: ?Sized>(lock: &RefCell<T>, _: Fairness) -> Ref<'_, T> {
+        lock.borrow()
+    }
+
+    fn try_read_lock<T: ?Sized>(lock: &RefCell<T>, _: Fairness) -> Option<Ref<'_, T>> {
+        lock.try_borrow().ok()
+    }
+
+    fn try_write_lock<T: ?Sized>(lock: &RefCell<T>) -> Option<RefMut<'_, T>> {
+        lock.try_borrow_mut().ok()
+    }
+
+    fn data_ptr<T: ?Sized>(lock: &RefCell<T>) -> *mut T {
+        lock.as_ptr()
+    }
+
+    fn unlock_write<T: ?Sized>(guard: RefMut<'_, T>, _: Fairness) {
+        drop(guard)
+    }
+
+    fn lock_state<T: ?Sized>(lock: &RefCell<T>) -> LockState {
+        if lock.try_borrow_mut().is_ok() {
+            LockState::Unlocked
+        } else if lock.try_borrow().is_ok() {
+            LockState::Shared
+        } else {
+            LockState::Exclusive
+        }
+    }
+
+    fn register_lock(name: &str, probe: Probe) -> Registration {
+        Registration::new_local(name, probe)
+    }
+
+    define_shared_mut!(SharedPtr, WeakPtr, Rc, Weak, RefCell, borrow, borrow_mut, Ref, RefMut);
+
+    impl<T> SharedPtr<T> {
+        /// Whether dropping this pointer would run `on_last_drop` finalizers.
+        #[cfg(feature = "rwlock")]
+        pub(crate) fn finalizes_on_drop(&self) -> bool {
+            Rc::strong_count(&self.0) == 1 && !self.0.finalizers.is_empty()
+        }
+    }
+
+    define_shared_history!(Ref);
+
+    define_split!(Ref);
+
+    define_double_buffered!(Rc);
+
+    define_frozen!(Rc);
+
+    define_store!(Rc, Ref);
+
+    define_mapped!(Rc);
+
+    define_shared_lazy!();
+
+    define_shared_once!(Rc, cell::OnceCell);
+
+    define_shared_uninit!();
+
+    define_revocable!();
+
+    define_shared_tuple!();
+
+    #[test]
+    fn test_auto_traits() {
+        assert!(!implements!(SharedPtr<u32>: Send));
+        assert!(!implements!(SharedPtr<u32>: Sync));
+        assert!(!implements!(Ref<'static, u32>: Send));
+        assert!(!implements!(WriteGuard<'static, u32>: Send));
+    }
+}
+
+#[cfg(feature = "mutex")]
+pub mod arc_mutex {
+    use std::sync::{
+        Arc,
+        Weak,
+    };
+
+    use crate::deps::parking_lot::{
+        Mutex,
+        MutexGuard,
+    };
+    use crate::fairness::Fairness;
+    use crate::registry::{
+        LockState,
+        Probe,
+        Registration,
+    };
+
+    pub type ReadGuard<'a, T> = crate::fairness::ReadGuard<MutexGuard<'a, T>>;
+
+    pub type FieldRef<'a, T, V> = crate::field::FieldGuard<ReadGuard<'a, T>, V>;
+
+    pub type FieldRefMut<'a, T, V> = crate::field::FieldGuardMut<WriteGuard<'a, T>, V>;
+
+    pub type SplitRefMut<'a, T, V> = crate::field::SplitGuardMut<WriteGuard<'a, T>, V>;
+
+    type Observer<T> = dyn Fn(&T) -> bool + Send + Sync;
+
+    fn read_lock<T: ?Sized>(lock: &Mutex<T>, fairness: Fairness) -> ReadGuard<'_, T> {
+        ReadGuard::new(lock.lock(), fairness)
+    }
+
+    fn try_read_lock<T: ?Sized>(lock: &Mutex<T>, fairness: Fairness) -> Option<ReadGuard<'_, T>> {
+        lock.try_lock().map(|guard| ReadGuard::new(guard, fairness))
+    }
+
+    fn try_write_lock<T: ?Sized>(lock: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+        lock.try_lock()
+    }
+
+    fn data_ptr<T: ?Sized>(lock: &Mutex<T>) -> *mut T {
+        lock.data_ptr()
+    }
+
+    fn unlock_write<T: ?Sized>(guard: MutexGuard<'_, T>, fairness: Fairness) {
+        match fairness {
+            Fairness::Fair => MutexGuard::unlock_fair(guard),
+            Fairness::WriterPreferring | Fairness::ReaderPreferring => drop(guard),
+        }
+    }
+
+    fn lock_state<T: ?Sized>(lock: &Mutex<T>) -> LockState {
+        if lock.is_locked() {
+            LockState::Exclusive
+        } else {
+            LockState::Unlocked
+        }
+    }
+
+    fn register_lock(name: &str, probe: Probe) -> Registration {
+        Registration::new(name, probe)
+    }
+
+    define_shared_mut!(
+        SharedPtr,
+        WeakPtr,
+        Arc,
+        Weak,
+        Mutex,
+        lock,
+        lock,
+        ReadGuard,
+        MutexGuard,
+        Send
+    );
+
+    define_shared_history!(ReadGuard);
+
+    define_split!(ReadGuard);
+
+    define_static_shared!(Mutex, const_mutex, lock, ReadGuard, MutexGuard);
+
+    define_double_buffered!(Arc);
+
+    define_frozen!(Arc, Send);
+
+    define_store!(Arc, ReadGuard, Send, Sync);
+
+    define_mapped!(Arc, Send, Sync);
+
+    define_shared_lazy!(Send);
+
+    define_shared_once!(Arc, sync::OnceLock);
+
+    define_shared_uninit!();
+
+    define_revocable!();
+
+    define_shared_tuple!();
+
+    #[test]
+    fn test_auto_traits() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedPtr<u32>>();
+        assert_send_sync::<SharedPtr<std::cell::Cell<u32>>>();
+
+        assert!(!implements!(SharedPtr<std::rc::Rc<u32>>: Send));
+        assert!(!implements!(SharedPtr<std::rc::Rc<u32>>: Sync));
+
+        let send_guard = cfg!(feature = "send_guard");
+        assert_eq!(implements!(MutexGuard<'static, u32>: Send), send_guard);
+        assert_eq!(implements!(ReadGuard<'static, u32>: Send), send_guard);
+        assert_eq!(implements!(WriteGuard<'static, u32>: Send), send_guard);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_assert_writer_thread() {
+        let ptr = SharedPtr::new(0u32);
+        ptr.assert_writer_thread();
+        *ptr.write() += 1;
+
+        let other = ptr.clone();
+        let result = std::thread::spawn(move || *other.write() += 1).join();
+        assert!(result.is_err());
+        assert_eq!(*ptr.read(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_upgrade_or_init() {
+        use std::sync::atomic::{
+            AtomicUsize,
+            Ordering,
+        };
+
+        use crate::by_address::Address;
+
+        let weak = Arc::new(WeakPtr::new());
+        let inits = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (weak, inits) = (weak.clone(), inits.clone());
+                std::thread::spawn(move || {
+                    weak.upgrade_or_init(|| {
+                        inits.fetch_add(1, Ordering::SeqCst);
+                        7u32
+                    })
+                })
+            })
+            .collect();
+        let values: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(inits.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|value| value.address() == values[0].address()));
+
+        drop(values);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(*weak.upgrade_or_init(|| 8).read(), 8);
+    }
+}
+
+#[cfg(feature = "rwlock")]
+pub mod arc_rwlock {
+    use std::sync::{
+        Arc,
+        Weak,
+    };
 
     use crate::deps::parking_lot::{
         RwLock,
         RwLockReadGuard,
         RwLockWriteGuard,
     };
+    use crate::fairness::Fairness;
+    use crate::registry::{
+        LockState,
+        Probe,
+        Registration,
+    };
+
+    pub type ReadGuard<'a, T> = crate::fairness::ReadGuard<RwLockReadGuard<'a, T>>;
+
+    pub type FieldRef<'a, T, V> = crate::field::FieldGuard<ReadGuard<'a, T>, V>;
+
+    pub type FieldRefMut<'a, T, V> = crate::field::FieldGuardMut<WriteGuard<'a, T>, V>;
+
+    pub type SplitRefMut<'a, T, V> = crate::field::SplitGuardMut<WriteGuard<'a, T>, V>;
 
-    pub type FieldRef<'a, T, V> = OwningRef<RwLockReadGuard<'a, T>, V>;
+    type Observer<T> = dyn Fn(&T) -> bool + Send + Sync;
+
+    fn read_lock<T: ?Sized>(lock: &RwLock<T>, fairness: Fairness) -> ReadGuard<'_, T> {
+        let guard = match fairness {
+            // Recursive reads don't queue behind parked writers.
+            Fairness::ReaderPreferring => lock.read_recursive(),
+            Fairness::WriterPreferring | Fairness::Fair => lock.read(),
+        };
+        ReadGuard::new(guard, fairness)
+    }
+
+    fn try_read_lock<T: ?Sized>(lock: &RwLock<T>, fairness: Fairness) -> Option<ReadGuard<'_, T>> {
+        let guard = match fairness {
+            Fairness::ReaderPreferring => lock.try_read_recursive(),
+            Fairness::WriterPreferring | Fairness::Fair => lock.try_read(),
+        };
+        guard.map(|guard| ReadGuard::new(guard, fairness))
+    }
+
+    fn try_write_lock<T: ?Sized>(lock: &RwLock<T>) -> Option<RwLockWriteGuard<'_, T>> {
+        lock.try_write()
+    }
+
+    fn data_ptr<T: ?Sized>(lock: &RwLock<T>) -> *mut T {
+        lock.data_ptr()
+    }
+
+    fn unlock_write<T: ?Sized>(guard: RwLockWriteGuard<'_, T>, fairness: Fairness) {
+        match fairness {
+            Fairness::Fair => RwLockWriteGuard::unlock_fair(guard),
+            Fairness::WriterPreferring | Fairness::ReaderPreferring => drop(guard),
+        }
+    }
+
+    fn lock_state<T: ?Sized>(lock: &RwLock<T>) -> LockState {
+        if lock.is_locked_exclusive() {
+            LockState::Exclusive
+        } else if lock.is_locked() {
+            LockState::Shared
+        } else {
+            LockState::Unlocked
+        }
+    }
+
+    fn register_lock(name: &str, probe: Probe) -> Registration {
+        Registration::new(name, probe)
+    }
 
     define_shared_mut!(
         SharedPtr,
@@ -265,7 +2307,52 @@ pub mod arc_rwlock {
         RwLock,
         read,
         write,
-        RwLockReadGuard,
+        ReadGuard,
+        RwLockWriteGuard,
+        Send
+    );
+
+    define_shared_history!(ReadGuard);
+
+    define_split!(ReadGuard);
+
+    define_static_shared!(
+        RwLock,
+        const_rwlock,
+        write,
+        ReadGuard,
         RwLockWriteGuard
     );
+
+    define_double_buffered!(Arc);
+
+    define_frozen!(Arc, Send);
+
+    define_store!(Arc, ReadGuard, Send, Sync);
+
+    define_mapped!(Arc, Send, Sync);
+
+    define_shared_lazy!(Send);
+
+    define_shared_once!(Arc, sync::OnceLock);
+
+    define_shared_uninit!();
+
+    define_revocable!();
+
+    define_shared_tuple!();
+
+    #[test]
+    fn test_auto_traits() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedPtr<u32>>();
+
+        assert!(!implements!(SharedPtr<std::cell::Cell<u32>>: Send));
+        assert!(!implements!(SharedPtr<std::cell::Cell<u32>>: Sync));
+
+        let send_guard = cfg!(feature = "send_guard");
+        assert_eq!(implements!(RwLockReadGuard<'static, u32>: Send), send_guard);
+        assert_eq!(implements!(ReadGuard<'static, u32>: Send), send_guard);
+        assert_eq!(implements!(WriteGuard<'static, u32>: Send), send_guard);
+    }
 }
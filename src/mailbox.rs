@@ -0,0 +1,74 @@
+//! Queue of deferred mutations, see `SharedPtr::queue_write`.
+//!
+//! A Treiber stack: producers push with a single compare-and-swap and never wait on the lock or
+//! on each other's closures. The lock holder takes the whole stack with one swap and reverses it
+//! to recover submission order, so there is no pop racing a push and no ABA problem.
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{
+    AtomicPtr,
+    Ordering,
+};
+
+struct Node<F: ?Sized> {
+    next: *mut Node<F>,
+    mutation: Box<F>,
+}
+
+pub(crate) struct Mailbox<F: ?Sized> {
+    head: AtomicPtr<Node<F>>,
+    // Queued closures are handed from the threads that push them to whichever thread drains the
+    // mailbox, so it is only `Send` and `Sync` when they are `Send`.
+    _queued: PhantomData<std::sync::Mutex<Box<F>>>,
+}
+
+impl<F: ?Sized> Mailbox<F> {
+    pub(crate) fn new() -> Self {
+        Mailbox {
+            head: AtomicPtr::new(ptr::null_mut()),
+            _queued: PhantomData,
+        }
+    }
+
+    pub(crate) fn push(&self, mutation: Box<F>) {
+        let node = Box::into_raw(Box::new(Node {
+            next: ptr::null_mut(),
+            mutation,
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: `node` is not shared until the exchange below succeeds.
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    /// Removes every queued mutation, oldest first.
+    pub(crate) fn take(&self) -> Vec<Box<F>> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut mutations = Vec::new();
+        while !node.is_null() {
+            // SAFETY: the swap detached the list, so every node in it is now exclusively ours
+            // and was allocated by `push`.
+            let Node { next, mutation } = *unsafe { Box::from_raw(node) };
+            mutations.push(mutation);
+            node = next;
+        }
+        mutations.reverse();
+        mutations
+    }
+}
+
+impl<F: ?Sized> Drop for Mailbox<F> {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
@@ -0,0 +1,361 @@
+//! Owned handles onto part of a pointer's value.
+//!
+//! `SharedPtr::project` returns a `MappedSharedPtr<V>` that keeps the parent pointer alive and
+//! locks it on every access, but only hands out the `V` selected by the projection. The parent's
+//! value type is erased, so a module can be given a pointer to `config.http` without knowing
+//! about the rest of the config.
+//!
+//! A projection is a pair of closures selecting the same part of the value, one through a shared
+//! and one through a mutable borrow: `read()` on a mapped pointer takes the parent's read lock
+//! and applies the first, `write()` takes the parent's write lock and applies the second.
+//!
+//! `MappedSharedPtr::downgrade` gives a `WeakField<V>`, which holds a weak pointer to the parent
+//! instead, so a subscriber to one field doesn't keep the whole value alive.
+use std::marker::PhantomData;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use std::ptr::NonNull;
+
+use crate::field::StableGuard;
+
+/// Locks the parent of a `MappedSharedPtr` and applies its projection. `W` is the backend's
+/// `WeakField<V>`.
+pub(crate) trait Project<V: ?Sized, W> {
+    fn read(&self) -> MappedRef<'_, V>;
+
+    #[track_caller]
+    fn write(&self) -> MappedRefMut<'_, V>;
+
+    fn downgrade(&self) -> W;
+}
+
+/// Upgrades the parent of a `WeakField` back into the backend's `MappedSharedPtr`, `S`.
+pub(crate) trait Upgrade<S> {
+    fn upgrade(&self) -> Option<S>;
+}
+
+/// A parent pointer paired with the projection applied to its value. The projection is behind
+/// an `Rc` or `Arc` so strong and weak handles can share it.
+pub(crate) struct Projection<P, F> {
+    pub(crate) parent: P,
+    pub(crate) project: F,
+}
+
+/// The shared and the mutable half of a projection.
+pub(crate) struct Lens<R, W> {
+    pub(crate) read: R,
+    pub(crate) write: W,
+}
+
+// Implemented for every type, so a guard can be boxed without naming the value it locks.
+trait Held {}
+
+impl<G> Held for G {}
+
+/// Shared access to the `V` behind a `MappedSharedPtr`.
+pub struct MappedRef<'a, V: ?Sized> {
+    field: NonNull<V>,
+    _guard: Box<dyn Held + 'a>,
+    _invariant: PhantomData<&'a mut V>,
+}
+
+impl<'a, V: ?Sized> MappedRef<'a, V> {
+    pub(crate) fn new<G>(guard: G, project: impl FnOnce(&G::Target) -> &V) -> Self
+    where
+        G: StableGuard + 'a,
+    {
+        let field = NonNull::from(project(&*guard));
+        MappedRef {
+            field,
+            _guard: Box::new(guard),
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<V: ?Sized> Deref for MappedRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: `field` was derived from a shared borrow of the locked value, which stays
+        // locked and in place for as long as `_guard` is held.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+impl<V> std::fmt::Debug for MappedRef<'_, V>
+where
+    V: ?Sized + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to the `V` behind a `MappedSharedPtr`. Releasing it has the same effects as
+/// releasing a `write()` guard of the parent.
+pub struct MappedRefMut<'a, V: ?Sized> {
+    field: NonNull<V>,
+    _guard: Box<dyn Held + 'a>,
+    _invariant: PhantomData<&'a mut V>,
+}
+
+impl<'a, V: ?Sized> MappedRefMut<'a, V> {
+    pub(crate) fn new<G>(mut guard: G, project: impl FnOnce(&mut G::Target) -> &mut V) -> Self
+    where
+        G: StableGuard + DerefMut + 'a,
+    {
+        let field = NonNull::from(project(&mut *guard));
+        MappedRefMut {
+            field,
+            _guard: Box::new(guard),
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<V: ?Sized> Deref for MappedRefMut<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: see `deref_mut`.
+        unsafe { self.field.as_ref() }
+    }
+}
+
+impl<V: ?Sized> DerefMut for MappedRefMut<'_, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        // SAFETY: `field` was derived from an exclusive borrow of the locked value, which stays
+        // locked and in place for as long as `_guard` is held, and `_guard` is not touched again
+        // until it is dropped.
+        unsafe { self.field.as_mut() }
+    }
+}
+
+impl<V> std::fmt::Debug for MappedRefMut<'_, V>
+where
+    V: ?Sized + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+macro_rules! define_mapped {
+    ($ptr:ident $(, $bound:ident)*) => {
+        /// A cloneable handle onto part of the value of a `SharedPtr`, created by
+        /// `SharedPtr::project`. See the `mapped` module.
+        pub struct MappedSharedPtr<V: ?Sized> {
+            parent: $ptr<dyn crate::mapped::Project<V, WeakField<V>> $(+ $bound)*>,
+        }
+
+        /// A weak handle onto part of the value of a `SharedPtr`, created by
+        /// `MappedSharedPtr::downgrade`. It does not keep the value alive.
+        pub struct WeakField<V: ?Sized> {
+            parent: $ptr<dyn crate::mapped::Upgrade<MappedSharedPtr<V>> $(+ $bound)*>,
+        }
+
+        impl<V: ?Sized> MappedSharedPtr<V> {
+            /// Read-locks the parent and returns a guard onto the projected part.
+            pub fn read(&self) -> crate::mapped::MappedRef<'_, V> {
+                self.parent.read()
+            }
+
+            #[track_caller]
+            pub fn write(&self) -> crate::mapped::MappedRefMut<'_, V> {
+                self.parent.write()
+            }
+
+            pub fn downgrade(&self) -> WeakField<V> {
+                self.parent.downgrade()
+            }
+        }
+
+        impl<V: ?Sized> WeakField<V> {
+            /// Returns a strong handle onto the field, or `None` once the value has been dropped.
+            pub fn upgrade(&self) -> Option<MappedSharedPtr<V>> {
+                self.parent.upgrade()
+            }
+        }
+
+        impl<V: ?Sized> crate::collections::Downgrade for MappedSharedPtr<V> {
+            type Weak = WeakField<V>;
+
+            fn downgrade(&self) -> WeakField<V> {
+                MappedSharedPtr::downgrade(self)
+            }
+
+            fn upgrade(weak: &WeakField<V>) -> Option<Self> {
+                weak.upgrade()
+            }
+        }
+
+        impl<V: ?Sized> Clone for MappedSharedPtr<V> {
+            fn clone(&self) -> Self {
+                MappedSharedPtr {
+                    parent: self.parent.clone(),
+                }
+            }
+        }
+
+        impl<V> std::fmt::Debug for MappedSharedPtr<V>
+        where
+            V: ?Sized + std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("MappedSharedPtr").field(&&*self.read()).finish()
+            }
+        }
+
+        impl<V: ?Sized> Clone for WeakField<V> {
+            fn clone(&self) -> Self {
+                WeakField {
+                    parent: self.parent.clone(),
+                }
+            }
+        }
+
+        impl<V: ?Sized> std::fmt::Debug for WeakField<V> {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("WeakField")
+            }
+        }
+
+        impl<T, V, R, W> crate::mapped::Project<V, WeakField<V>>
+            for crate::mapped::Projection<SharedPtr<T>, $ptr<crate::mapped::Lens<R, W>>>
+        where
+            T: ?Sized + 'static,
+            V: ?Sized,
+            R: Fn(&T) -> &V $(+ $bound)* + 'static,
+            W: Fn(&mut T) -> &mut V $(+ $bound)* + 'static,
+            SharedPtr<T>: 'static $(+ $bound)*,
+            WeakPtr<T>: 'static $(+ $bound)*,
+        {
+            fn read(&self) -> crate::mapped::MappedRef<'_, V> {
+                crate::mapped::MappedRef::new(self.parent.read(), &self.project.read)
+            }
+
+            #[track_caller]
+            fn write(&self) -> crate::mapped::MappedRefMut<'_, V> {
+                crate::mapped::MappedRefMut::new(self.parent.write(), &self.project.write)
+            }
+
+            fn downgrade(&self) -> WeakField<V> {
+                let projection = crate::mapped::Projection {
+                    parent: WeakPtr::downgrade(&self.parent),
+                    project: self.project.clone(),
+                };
+                WeakField {
+                    parent: $ptr::new(projection),
+                }
+            }
+        }
+
+        impl<T, V, R, W> crate::mapped::Upgrade<MappedSharedPtr<V>>
+            for crate::mapped::Projection<WeakPtr<T>, $ptr<crate::mapped::Lens<R, W>>>
+        where
+            T: ?Sized + 'static,
+            V: ?Sized,
+            R: Fn(&T) -> &V $(+ $bound)* + 'static,
+            W: Fn(&mut T) -> &mut V $(+ $bound)* + 'static,
+            SharedPtr<T>: 'static $(+ $bound)*,
+            WeakPtr<T>: 'static $(+ $bound)*,
+        {
+            fn upgrade(&self) -> Option<MappedSharedPtr<V>> {
+                let projection = crate::mapped::Projection {
+                    parent: self.parent.upgrade()?,
+                    project: self.project.clone(),
+                };
+                Some(MappedSharedPtr {
+                    parent: $ptr::new(projection),
+                })
+            }
+        }
+
+        impl<T: ?Sized + 'static> SharedPtr<T> {
+            /// Returns a handle that shares this pointer's value but only gives access to the
+            /// part selected by `read` and `write`, which must select the same part. The handle
+            /// keeps the value alive like any other clone.
+            pub fn project<V, R, W>(&self, read: R, write: W) -> MappedSharedPtr<V>
+            where
+                V: ?Sized,
+                R: Fn(&T) -> &V $(+ $bound)* + 'static,
+                W: Fn(&mut T) -> &mut V $(+ $bound)* + 'static,
+                SharedPtr<T>: 'static $(+ $bound)*,
+                WeakPtr<T>: 'static $(+ $bound)*,
+            {
+                let projection = crate::mapped::Projection {
+                    parent: self.clone(),
+                    project: $ptr::new(crate::mapped::Lens { read, write }),
+                };
+                MappedSharedPtr {
+                    parent: $ptr::new(projection),
+                }
+            }
+        }
+
+        #[test]
+        fn test_project() {
+            #[derive(Debug, Default)]
+            struct Http {
+                port: u16,
+            }
+
+            #[derive(Debug, Default)]
+            struct Config {
+                name: String,
+                http: Http,
+            }
+
+            let config = SharedPtr::new(Config::default());
+            let http = config.project(|config| &config.http, |config| &mut config.http);
+            let port = http.clone();
+            drop(config.clone());
+
+            port.write().port = 8080;
+            assert_eq!(config.read().http.port, 8080);
+            config.write().name.push_str("server");
+            assert_eq!(http.read().port, 8080);
+            assert_eq!(format!("{:?}", http), "MappedSharedPtr(Http { port: 8080 })");
+
+            let version = config.version();
+            drop(http.read());
+            assert_eq!(config.version(), version);
+            drop(http.write());
+            assert_eq!(config.version(), version + 1);
+
+            drop(config);
+            http.write().port += 1;
+            assert_eq!(port.read().port, 8081);
+        }
+
+        #[test]
+        fn test_weak_field() {
+            let pair = SharedPtr::new((0u32, String::new()));
+            let label = pair.project(|pair| &pair.1, |pair| &mut pair.1).downgrade();
+            assert!(label.clone().upgrade().is_some());
+
+            label.upgrade().unwrap().write().push_str("up");
+            assert_eq!(pair.read().1, "up");
+
+            let held = label.upgrade().unwrap();
+            drop(pair);
+            assert_eq!(*held.read(), "up");
+            drop(held);
+            assert!(label.upgrade().is_none());
+        }
+    };
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_mapped_read_shares_lock() {
+    use crate::arc_rwlock::SharedPtr;
+
+    let pair = SharedPtr::new((1u32, 2u32));
+    let first = pair.project(|pair| &pair.0, |pair| &mut pair.0);
+    let held = first.read();
+    assert_eq!((*held, pair.read().1), (1, 2));
+}
@@ -0,0 +1,461 @@
+//! Change notification plumbing shared by every backend.
+//!
+//! Each pointer keeps a list of observers that are called with the value whenever a write guard
+//! is released. `Changes` is the async-facing consumer built on top of it.
+use std::cmp::Reverse;
+use std::collections::{
+    BTreeMap,
+    BinaryHeap,
+    HashMap,
+    VecDeque,
+};
+use std::hash::{
+    BuildHasher,
+    Hash,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Once,
+};
+use std::task::{
+    Context,
+    Poll,
+    Waker,
+};
+
+use crate::deps::parking_lot::{
+    const_mutex,
+    Condvar,
+    Mutex,
+    MutexGuard,
+};
+
+pub(crate) struct Observers<F: ?Sized> {
+    list: Mutex<Vec<Box<F>>>,
+    // Lets writes to pointers nobody observes skip the lock.
+    any: AtomicBool,
+}
+
+impl<F: ?Sized> Observers<F> {
+    pub(crate) fn new() -> Self {
+        Observers {
+            list: Mutex::new(Vec::new()),
+            any: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn push(&self, observer: Box<F>) {
+        let mut list = self.list.lock();
+        list.push(observer);
+        self.any.store(true, Ordering::Release);
+    }
+
+    /// Calls `notify` with every observer, dropping those for which it returns false.
+    pub(crate) fn retain(&self, mut notify: impl FnMut(&F) -> bool) {
+        if !self.any.load(Ordering::Acquire) {
+            return;
+        }
+        let mut list = self.list.lock();
+        list.retain(|observer| notify(observer));
+        if list.is_empty() {
+            self.any.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Wakes the consumers of debounced channels once their deadlines have passed. One thread serves
+/// every channel in the process. It is started when the first debounced channel is created,
+/// never from `Publisher::send`, which runs while the pointer's write lock is held.
+struct Timer {
+    state: Mutex<TimerState>,
+    wakeup: Condvar,
+}
+
+struct TimerState {
+    /// The current deadline and waker of each channel, by channel id.
+    pending: BTreeMap<u64, (Instant, Waker)>,
+    /// Every deadline set, earliest first. Entries that no longer match `pending` are skipped.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+}
+
+static TIMER: Timer = Timer {
+    state: const_mutex(TimerState {
+        pending: BTreeMap::new(),
+        deadlines: BinaryHeap::new(),
+    }),
+    wakeup: Condvar::new(),
+};
+
+impl Timer {
+    fn start() -> &'static Timer {
+        static STARTED: Once = Once::new();
+        STARTED.call_once(|| {
+            std::thread::Builder::new()
+                .name("shared-ptrs-debounce".into())
+                .spawn(|| TIMER.run())
+                .expect("failed to spawn the debounce timer");
+        });
+        &TIMER
+    }
+
+    /// Wakes `waker` at `deadline` instead of whatever was set before for `channel`.
+    fn set(&self, channel: u64, deadline: Instant, waker: Waker) {
+        let mut state = self.state.lock();
+        let earliest = state.deadlines.peek().is_none_or(|&Reverse((next, _))| deadline < next);
+        state.pending.insert(channel, (deadline, waker));
+        state.deadlines.push(Reverse((deadline, channel)));
+        if earliest {
+            self.wakeup.notify_one();
+        }
+    }
+
+    fn cancel(&self, channel: u64) {
+        self.state.lock().pending.remove(&channel);
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock();
+        loop {
+            let Reverse((deadline, channel)) = match state.deadlines.peek() {
+                Some(&next) => next,
+                None => {
+                    self.wakeup.wait(&mut state);
+                    continue;
+                }
+            };
+            if state.pending.get(&channel).map(|&(current, _)| current) != Some(deadline) {
+                state.deadlines.pop();
+            } else if Instant::now() < deadline {
+                self.wakeup.wait_until(&mut state, deadline);
+            } else {
+                state.deadlines.pop();
+                if let Some((_, waker)) = state.pending.remove(&channel) {
+                    MutexGuard::unlocked(&mut state, || waker.wake());
+                }
+            }
+        }
+    }
+}
+
+struct Channel<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    closed: bool,
+    /// When set, a send replaces any queued value and holds it back until no further send has
+    /// happened for this long.
+    window: Option<Duration>,
+    deadline: Option<Instant>,
+    /// Identifies the channel to the timer, which is only set for debounced channels.
+    id: u64,
+    timer: Option<&'static Timer>,
+}
+
+impl<T> Channel<T> {
+    /// Returns the next value, or the instant it becomes available when debouncing.
+    fn pop_ready(&mut self) -> Result<Option<T>, Instant> {
+        match self.deadline {
+            Some(deadline) if !self.closed && Instant::now() < deadline => Err(deadline),
+            _ => {
+                self.deadline = None;
+                Ok(self.queue.pop_front())
+            }
+        }
+    }
+}
+
+/// Sending half of a `Changes` stream, owned by the pointer's observer list.
+pub(crate) struct Publisher<T>(Arc<Mutex<Channel<T>>>);
+
+impl<T> Publisher<T> {
+    /// Whether the receiving `Changes` handle still exists.
+    pub(crate) fn is_open(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+
+    pub(crate) fn send(&self, value: T) {
+        let waker = {
+            let mut channel = self.0.lock();
+            match channel.window {
+                None => {
+                    channel.queue.push_back(value);
+                    channel.waker.take()
+                }
+                Some(window) => {
+                    channel.queue.clear();
+                    channel.queue.push_back(value);
+                    let deadline = Instant::now() + window;
+                    channel.deadline = Some(deadline);
+                    // A waiting consumer is only woken once the window has passed.
+                    if let (Some(timer), Some(waker)) = (channel.timer, channel.waker.clone()) {
+                        timer.set(channel.id, deadline, waker);
+                    }
+                    None
+                }
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer {
+            timer.cancel(self.id);
+        }
+    }
+}
+
+impl<T> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut channel = self.0.lock();
+            channel.closed = true;
+            channel.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) fn channel<T>(window: Option<Duration>) -> (Publisher<T>, Changes<T>) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let channel = Arc::new(Mutex::new(Channel {
+        queue: VecDeque::new(),
+        waker: None,
+        closed: false,
+        window,
+        deadline: None,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        timer: window.map(|_| Timer::start()),
+    }));
+    (Publisher(channel.clone()), Changes(channel))
+}
+
+/// A stream of snapshots of a pointer's value, one per released write guard.
+///
+/// Debounced streams (see `changes_debounced`) only hold the most recent snapshot and release it
+/// once writes have been quiet for the configured window.
+///
+/// The stream ends once every strong reference to the pointer has been dropped. `poll_next`
+/// has the same contract as `futures::Stream::poll_next`, so adapting it to the futures
+/// ecosystem is a one-line impl.
+pub struct Changes<T>(Arc<Mutex<Channel<T>>>);
+
+impl<T> Changes<T> {
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut channel = self.0.lock();
+        match channel.pop_ready() {
+            Ok(Some(value)) => Poll::Ready(Some(value)),
+            Ok(None) if channel.closed => Poll::Ready(None),
+            Ok(None) => {
+                channel.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(deadline) => {
+                channel.waker = Some(cx.waker().clone());
+                if let Some(timer) = channel.timer {
+                    timer.set(channel.id, deadline, cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Resolves to the next snapshot, or `None` once the pointer is gone.
+    pub fn next_change(&mut self) -> NextChange<'_, T> {
+        NextChange(self)
+    }
+
+    /// Returns the next snapshot if one is ready to be delivered.
+    pub fn try_next(&mut self) -> Option<T> {
+        self.0.lock().pop_ready().unwrap_or(None)
+    }
+}
+
+impl<T> std::fmt::Debug for Changes<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let channel = self.0.lock();
+        f.debug_struct("Changes")
+            .field("pending", &channel.queue.len())
+            .field("closed", &channel.closed)
+            .finish()
+    }
+}
+
+/// Future returned by `Changes::next_change`.
+#[derive(Debug)]
+pub struct NextChange<'a, T>(&'a mut Changes<T>);
+
+impl<T> Future for NextChange<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll_next(cx)
+    }
+}
+
+/// Values that can describe how they changed, so observers of large states receive deltas
+/// instead of full copies. See `SharedPtr::deltas`.
+pub trait Diff {
+    type Delta;
+
+    /// Returns what changed from `previous` to `self`, or `None` if nothing did.
+    fn diff(&self, previous: &Self) -> Option<Self::Delta>;
+
+    /// Applies a delta produced by `diff`.
+    fn apply(&mut self, delta: &Self::Delta);
+}
+
+/// Changes between two versions of a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapDelta<K, V> {
+    /// Entries that were inserted or whose value changed.
+    pub upserted: Vec<(K, V)>,
+    pub removed: Vec<K>,
+}
+
+impl<K, V> MapDelta<K, V> {
+    fn into_option(self) -> Option<Self> {
+        if self.upserted.is_empty() && self.removed.is_empty() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+impl<K, V, S> Diff for HashMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: PartialEq + Clone,
+    S: BuildHasher,
+{
+    type Delta = MapDelta<K, V>;
+
+    fn diff(&self, previous: &Self) -> Option<Self::Delta> {
+        MapDelta {
+            upserted: self
+                .iter()
+                .filter(|&(k, v)| previous.get(k) != Some(v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            removed: previous.keys().filter(|k| !self.contains_key(k)).cloned().collect(),
+        }
+        .into_option()
+    }
+
+    fn apply(&mut self, delta: &Self::Delta) {
+        for key in &delta.removed {
+            self.remove(key);
+        }
+        self.extend(delta.upserted.iter().cloned());
+    }
+}
+
+impl<K, V> Diff for BTreeMap<K, V>
+where
+    K: Ord + Clone,
+    V: PartialEq + Clone,
+{
+    type Delta = MapDelta<K, V>;
+
+    fn diff(&self, previous: &Self) -> Option<Self::Delta> {
+        MapDelta {
+            upserted: self
+                .iter()
+                .filter(|&(k, v)| previous.get(k) != Some(v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            removed: previous.keys().filter(|k| !self.contains_key(k)).cloned().collect(),
+        }
+        .into_option()
+    }
+
+    fn apply(&mut self, delta: &Self::Delta) {
+        for key in &delta.removed {
+            self.remove(key);
+        }
+        self.extend(delta.upserted.iter().cloned());
+    }
+}
+
+#[test]
+fn test_debounce_wakes_once() {
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+    use std::task::Wake;
+
+    struct Count(AtomicUsize);
+
+    impl Wake for Count {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let wakes = Arc::new(Count(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+    let (publisher, mut changes) = channel(Some(Duration::from_millis(100)));
+    assert_eq!(changes.poll_next(&mut cx), Poll::Pending);
+
+    for i in 0..5 {
+        publisher.send(i);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+    while wakes.0.load(Ordering::SeqCst) == 0 {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(changes.poll_next(&mut cx), Poll::Ready(Some(4)));
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_debounce_timer_orders_channels_by_deadline() {
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+
+    struct Count(AtomicUsize);
+
+    impl Wake for Count {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let slow_wakes = Arc::new(Count(AtomicUsize::new(0)));
+    let fast_wakes = Arc::new(Count(AtomicUsize::new(0)));
+    let slow_waker = Waker::from(slow_wakes.clone());
+    let fast_waker = Waker::from(fast_wakes.clone());
+    let (slow_publisher, mut slow) = channel(Some(Duration::from_secs(60)));
+    let (fast_publisher, mut fast) = channel(Some(Duration::from_millis(10)));
+    assert_eq!(slow.poll_next(&mut Context::from_waker(&slow_waker)), Poll::Pending);
+    assert_eq!(fast.poll_next(&mut Context::from_waker(&fast_waker)), Poll::Pending);
+
+    // The later deadline is set first, so the timer has to move its wakeup forward.
+    slow_publisher.send(1);
+    fast_publisher.send(2);
+    while fast_wakes.0.load(Ordering::SeqCst) == 0 {
+        std::thread::yield_now();
+    }
+    assert_eq!(fast.try_next(), Some(2));
+    assert_eq!(slow_wakes.0.load(Ordering::SeqCst), 0);
+    assert_eq!(slow.try_next(), None);
+}
@@ -0,0 +1,73 @@
+//! Pointers that are written once and then read without locking.
+
+macro_rules! define_shared_once {
+    ($ptr:ident, $module:ident::$cell:ident) => {
+        /// A pointer that accepts a single `set()` and afterwards serves `read()` without taking
+        /// any lock, for configuration that is only mutable during startup.
+        ///
+        /// Clones share the value.
+        pub struct SharedOnce<T>($ptr<std::$module::$cell<T>>);
+
+        impl<T> SharedOnce<T> {
+            pub fn new() -> Self {
+                SharedOnce($ptr::new(std::$module::$cell::new()))
+            }
+
+            /// Stores `value` unless one has already been set, in which case it is handed back.
+            pub fn set(&self, value: T) -> Result<(), T> {
+                self.0.set(value)
+            }
+
+            pub fn get(&self) -> Option<&T> {
+                self.0.get()
+            }
+
+            /// # Panics
+            ///
+            /// If `set()` has not been called yet.
+            pub fn read(&self) -> &T {
+                self.get().expect("SharedOnce read before it was set")
+            }
+
+            pub fn is_set(&self) -> bool {
+                self.get().is_some()
+            }
+        }
+
+        impl<T> Clone for SharedOnce<T> {
+            fn clone(&self) -> Self {
+                SharedOnce(self.0.clone())
+            }
+        }
+
+        impl<T> Default for SharedOnce<T> {
+            fn default() -> Self {
+                SharedOnce::new()
+            }
+        }
+
+        impl<T> std::fmt::Debug for SharedOnce<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match self.get() {
+                    Some(value) => f.debug_tuple("SharedOnce").field(value).finish(),
+                    None => f.write_str("SharedOnce(<unset>)"),
+                }
+            }
+        }
+
+        #[test]
+        fn test_shared_once_sets_once() {
+            let config = SharedOnce::new();
+            let other = config.clone();
+            assert_eq!(config.get(), None);
+
+            assert_eq!(other.set(String::from("prod")), Ok(()));
+            assert_eq!(config.set(String::from("dev")), Err(String::from("dev")));
+            assert_eq!(config.read(), "prod");
+            assert!(config.is_set());
+        }
+    };
+}
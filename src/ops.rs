@@ -0,0 +1,75 @@
+//! Compound assignment through the write lock, so `*ptr.write() += 1` can be written `ptr += 1`.
+//!
+//! Each operator takes the write lock for the one operation, with the same effects as any other
+//! `write()`. Enabled by the `ops` feature.
+use std::ops::{
+    AddAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+    DivAssign,
+    MulAssign,
+    RemAssign,
+    ShlAssign,
+    ShrAssign,
+    SubAssign,
+};
+
+macro_rules! impl_op_assign {
+    ($backend:ident: $($op:ident::$method:ident),*) => {
+        $(
+            impl<T, Rhs> $op<Rhs> for crate::$backend::SharedPtr<T>
+            where
+                T: $op<Rhs>,
+            {
+                #[track_caller]
+                fn $method(&mut self, rhs: Rhs) {
+                    self.write().$method(rhs)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_op_assigns {
+    ($($backend:ident),*) => {
+        $(
+            impl_op_assign!(
+                $backend: AddAssign::add_assign,
+                SubAssign::sub_assign,
+                MulAssign::mul_assign,
+                DivAssign::div_assign,
+                RemAssign::rem_assign,
+                BitAndAssign::bitand_assign,
+                BitOrAssign::bitor_assign,
+                BitXorAssign::bitxor_assign,
+                ShlAssign::shl_assign,
+                ShrAssign::shr_assign
+            );
+        )*
+    };
+}
+
+#[cfg(feature = "rc")]
+impl_op_assigns!(rc_refcell);
+#[cfg(feature = "mutex")]
+impl_op_assigns!(arc_mutex);
+#[cfg(feature = "rwlock")]
+impl_op_assigns!(arc_rwlock);
+
+#[test]
+#[cfg(all(feature = "rc", feature = "rwlock"))]
+fn test_op_assign() {
+    let mut counter = crate::arc_rwlock::SharedPtr::new(1u32);
+    let alias = counter.clone();
+    counter += 4;
+    counter *= 3;
+    counter -= 1;
+    counter <<= 1;
+    assert_eq!(*alias.read(), 28);
+
+    let mut flags = crate::rc_refcell::SharedPtr::new(0b0101u8);
+    flags |= 0b0010;
+    flags &= 0b0110;
+    assert_eq!(*flags.read(), 0b0110);
+}
@@ -0,0 +1,63 @@
+//! Cache line padding to prevent false sharing between pointers.
+
+/// Pads and aligns a value to the length of a cache line.
+///
+/// Pointers allocated back to back can share a cache line, so writes through one slow down
+/// readers and writers of its neighbours. `SharedPtr::new_padded` stores the value in a
+/// `CachePadded`, which over-aligns the whole allocation (counters, lock and value) so no two
+/// pointers ever share a line.
+///
+/// 128 bytes on x86_64 and aarch64, where the prefetcher pulls in cache lines in pairs, and 64
+/// bytes elsewhere.
+#[cfg_attr(any(target_arch = "x86_64", target_arch = "aarch64"), repr(align(128)))]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), repr(align(64)))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        CachePadded(value)
+    }
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+fn test_padded_pointers_do_not_share_lines() {
+    use crate::arc_mutex::SharedPtr;
+
+    let counters: Vec<SharedPtr<CachePadded<u64>>> =
+        (0..4).map(|_| SharedPtr::new_padded(0)).collect();
+    for counter in &counters {
+        **counter.write() += 1;
+        let address = std::ptr::addr_of!(**counter.read()).addr();
+        assert_eq!(address % std::mem::align_of::<CachePadded<u64>>(), 0);
+    }
+    assert!(std::mem::align_of::<CachePadded<u64>>() >= 64);
+    assert_eq!(**counters[0].read(), 1);
+}
@@ -0,0 +1,333 @@
+//! Checkpointing pointers to disk so that state survives a restart.
+//!
+//! A `Checkpointer` holds a set of named pointers. `save` writes each one to its own file in a
+//! directory, and `restore` reads them back into the same pointers on the next start:
+//!
+//! ```ignore
+//! let checkpointer = Checkpointer::new("state", Json);
+//! checkpointer.register("sessions", &sessions);
+//! checkpointer.register("config", &config);
+//! checkpointer.restore()?;
+//! let _periodic = checkpointer.spawn(Duration::from_secs(30));
+//! ```
+//!
+//! The crate doesn't depend on a serde format, so the caller supplies one as a `Format`. Files
+//! are replaced by renaming a fully written and synced temporary file over them, so a crash or
+//! power loss mid-save leaves the previous checkpoint intact. Each pointer is serialized under
+//! its own read lock; a save is not a consistent snapshot across pointers. Only the `arc_mutex`
+//! and `arc_rwlock` pointers can be registered, since saves may run on a background thread.
+use std::fs;
+use std::io::{
+    self,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::deps::parking_lot::Mutex;
+use crate::deps::serde::de::DeserializeOwned;
+use crate::deps::serde::Serialize;
+
+/// A serde data format, such as JSON or bincode, that checkpoints are written in.
+pub trait Format: Send + Sync + 'static {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> io::Result<Vec<u8>>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+/// Pointers that can be checkpointed: they serialize as their value and can have a restored
+/// value stored back into them.
+pub trait Persistent: Serialize + Send + Sync + 'static {
+    type Value: DeserializeOwned;
+
+    /// Stores `value` under the write lock, so every clone sees it.
+    fn replace(&self, value: Self::Value);
+}
+
+macro_rules! impl_persistent {
+    ($($backend:ident),*) => {
+        $(
+            impl<T> Persistent for crate::$backend::SharedPtr<T>
+            where
+                T: Serialize + DeserializeOwned + Send + Sync + 'static,
+            {
+                type Value = T;
+
+                fn replace(&self, value: T) {
+                    *self.write() = value;
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "mutex")]
+impl_persistent!(arc_mutex);
+#[cfg(feature = "rwlock")]
+impl_persistent!(arc_rwlock);
+
+type Save<F> = Box<dyn Fn(&F) -> io::Result<Vec<u8>> + Send + Sync>;
+
+type Restore<F> = Box<dyn Fn(&F, &[u8]) -> io::Result<()> + Send + Sync>;
+
+struct Entry<F> {
+    name: String,
+    save: Save<F>,
+    restore: Restore<F>,
+}
+
+struct Shared<F> {
+    dir: PathBuf,
+    format: F,
+    entries: Mutex<Vec<Entry<F>>>,
+}
+
+/// Saves and restores a set of named pointers. Clones share the set.
+pub struct Checkpointer<F>(Arc<Shared<F>>);
+
+impl<F: Format> Checkpointer<F> {
+    /// Creates a checkpointer writing to `dir`, which is created on the first save.
+    pub fn new(dir: impl Into<PathBuf>, format: F) -> Self {
+        Checkpointer(Arc::new(Shared {
+            dir: dir.into(),
+            format,
+            entries: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Adds `ptr` to the checkpoint as `name`, which is also its file name. Registering a name
+    /// again replaces the earlier pointer.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is not a plain file name, or starts with `.`, which is reserved for the
+    /// temporary files written during a save.
+    pub fn register<P: Persistent + Clone>(&self, name: &str, ptr: &P) {
+        assert!(
+            !name.is_empty() && !name.contains(['/', '\\']) && !name.starts_with('.'),
+            "checkpoint name {:?} is not a plain file name or starts with '.'",
+            name
+        );
+        let saved = ptr.clone();
+        let restored = ptr.clone();
+        let entry = Entry {
+            name: name.to_owned(),
+            save: Box::new(move |format: &F| format.encode(&saved)),
+            restore: Box::new(move |format: &F, bytes: &[u8]| {
+                restored.replace(format.decode(bytes)?);
+                Ok(())
+            }),
+        };
+        let mut entries = self.0.entries.lock();
+        entries.retain(|existing| existing.name != name);
+        entries.push(entry);
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.0.dir
+    }
+
+    /// Writes every registered pointer to disk. Stops at the first error, leaving the files of
+    /// pointers not yet saved as they were.
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.0.dir)?;
+        for entry in self.0.entries.lock().iter() {
+            let bytes = (entry.save)(&self.0.format)?;
+            let path = self.0.dir.join(&entry.name);
+            let temporary = self.0.dir.join(format!(".{}.tmp", entry.name));
+            let mut file = fs::File::create(&temporary)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            drop(file);
+            fs::rename(&temporary, &path)?;
+            sync_dir(&self.0.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the saved value of every registered pointer back into it and returns how many were
+    /// restored. Pointers that have never been saved are left alone.
+    pub fn restore(&self) -> io::Result<usize> {
+        let mut restored = 0;
+        for entry in self.0.entries.lock().iter() {
+            let bytes = match fs::read(self.0.dir.join(&entry.name)) {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+            (entry.restore)(&self.0.format, &bytes)?;
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Saves every `interval` on a background thread until the returned handle is dropped,
+    /// which also saves one last time.
+    pub fn spawn(&self, interval: Duration) -> Periodic {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let errors = Arc::new(Mutex::new(None));
+        let checkpointer = self.clone();
+        let last_error = errors.clone();
+        let thread = std::thread::Builder::new()
+            .name("shared-ptrs-checkpoint".into())
+            .spawn(move || loop {
+                let disconnected = match stopped.recv_timeout(interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
+                };
+                if let Err(error) = checkpointer.save() {
+                    *last_error.lock() = Some(error);
+                }
+                if disconnected {
+                    return;
+                }
+            })
+            .expect("failed to spawn the checkpoint thread");
+        Periodic {
+            stop: Some(stop),
+            thread: Some(thread),
+            errors,
+        }
+    }
+}
+
+/// Makes renames within `dir` durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened as files elsewhere; renames are as durable as the platform makes
+// them.
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+impl<F> Clone for Checkpointer<F> {
+    fn clone(&self) -> Self {
+        Checkpointer(self.0.clone())
+    }
+}
+
+impl<F> std::fmt::Debug for Checkpointer<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let entries = self.0.entries.lock();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        f.debug_struct("Checkpointer")
+            .field("dir", &self.0.dir)
+            .field("entries", &names)
+            .finish()
+    }
+}
+
+/// Handle to a background checkpoint thread, see `Checkpointer::spawn`.
+#[derive(Debug)]
+pub struct Periodic {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    errors: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Periodic {
+    /// Returns the error from the most recent failed save, if any, and clears it.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.errors.lock().take()
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "mutex", feature = "rwlock"))]
+fn test_checkpoint_round_trip() {
+    use crate::arc_rwlock::SharedPtr;
+    use crate::test_value::{
+        from_value,
+        to_value,
+        Value,
+    };
+
+    /// Writes unsigned integers and sequences of them as text, which is all this test stores.
+    struct Text;
+
+    fn render(value: &Value) -> String {
+        match value {
+            Value::U64(v) => v.to_string(),
+            Value::Seq(values) => {
+                let values: Vec<String> = values.iter().map(render).collect();
+                format!("[{}]", values.join(","))
+            }
+            other => panic!("unsupported value {:?}", other),
+        }
+    }
+
+    fn parse(text: &str) -> Value {
+        match text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+            Some("") => Value::Seq(Vec::new()),
+            Some(items) => Value::Seq(items.split(',').map(parse).collect()),
+            None => Value::U64(text.parse().unwrap()),
+        }
+    }
+
+    impl Format for Text {
+        fn encode<T: Serialize + ?Sized>(&self, value: &T) -> io::Result<Vec<u8>> {
+            let value = to_value(value).map_err(io::Error::other)?;
+            Ok(render(&value).into_bytes())
+        }
+
+        fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+            let value = parse(std::str::from_utf8(bytes).unwrap());
+            from_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("shared-ptrs-checkpoint-{}", std::process::id()));
+    let counter = SharedPtr::new(7u64);
+    let history = crate::arc_mutex::SharedPtr::new(vec![1u64, 2]);
+    let checkpointer = Checkpointer::new(&dir, Text);
+    // Would be clobbered by the temporary file of "counter" if that were named by replacing the
+    // extension.
+    checkpointer.register("counter.tmp", &SharedPtr::new(9u64));
+    checkpointer.register("counter", &counter);
+    checkpointer.register("history", &history);
+    checkpointer.save().unwrap();
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["counter", "counter.tmp", "history"]);
+    // Names of temporary files can't be registered.
+    let hidden = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        checkpointer.register(".counter.tmp", &SharedPtr::new(0u64))
+    }));
+    assert!(hidden.is_err());
+
+    let periodic = checkpointer.spawn(Duration::from_secs(3600));
+    *counter.write() = 8;
+    drop(periodic);
+
+    let counter = SharedPtr::new(0u64);
+    let history = crate::arc_mutex::SharedPtr::new(Vec::<u64>::new());
+    let fresh = Checkpointer::new(&dir, Text);
+    fresh.register("counter", &counter);
+    fresh.register("history", &history);
+    fresh.register("missing", &SharedPtr::new(0u64));
+    assert_eq!(fresh.restore().unwrap(), 2);
+    assert_eq!(*counter.read(), 8);
+    assert_eq!(*history.read(), [1, 2]);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,75 @@
+//! Copy-on-write pointers with O(1) snapshots, for persistent data structures.
+//!
+//! `SharedPersistent<T>` keeps its value behind an `Arc`. `snapshot()` clones the `Arc` under a
+//! momentary read lock, so readers such as analytics threads get a consistent view without
+//! holding the lock while they work. `write()` goes through `Arc::make_mut`, which clones the
+//! value only if a snapshot of it is still alive.
+//!
+//! For ordinary collections that clone is a full copy. Persistent collections like the `im`
+//! crate's `HashMap` and `Vector` clone in O(1) by sharing structure, which makes both snapshots
+//! and the writes that follow them cheap:
+//!
+//! ```ignore
+//! let index = SharedPersistent::new(im::HashMap::new());
+//! let view = index.snapshot();
+//! index.write().insert(key, value);
+//! ```
+use std::sync::Arc;
+
+use crate::arc_rwlock::{
+    FieldRefMut,
+    SharedPtr,
+};
+
+/// A pointer whose readers take cheap immutable snapshots. Clones share the value.
+pub struct SharedPersistent<T>(SharedPtr<Arc<T>>);
+
+impl<T> SharedPersistent<T> {
+    pub fn new(init: T) -> Self {
+        SharedPersistent(SharedPtr::new(Arc::new(init)))
+    }
+
+    /// Returns the current value. Later writes don't affect it.
+    pub fn snapshot(&self) -> Arc<T> {
+        self.0.read().clone()
+    }
+
+    /// Write-locks the value, first cloning it if any snapshot of it is still alive.
+    #[track_caller]
+    pub fn write(&self) -> FieldRefMut<'_, Arc<T>, T>
+    where
+        T: Clone,
+    {
+        self.0.write_field(Arc::make_mut)
+    }
+}
+
+impl<T> Clone for SharedPersistent<T> {
+    fn clone(&self) -> Self {
+        SharedPersistent(self.0.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for SharedPersistent<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("SharedPersistent").field(&*self.snapshot()).finish()
+    }
+}
+
+#[test]
+fn test_snapshots_are_isolated_from_writes() {
+    let index = SharedPersistent::new(vec![1u32]);
+    let before = index.snapshot();
+    index.write().push(2);
+    assert_eq!(*before, [1]);
+    assert_eq!(*index.snapshot(), [1, 2]);
+
+    // Without outstanding snapshots, writes happen in place.
+    drop(before);
+    let address = Arc::as_ptr(&index.snapshot());
+    index.write().push(3);
+    assert_eq!(Arc::as_ptr(&index.snapshot()), address);
+}
@@ -0,0 +1,138 @@
+//! Per-thread snapshots for values that are read far more often than they are written.
+//!
+//! Every thread that reads a `SharedReadMostly` keeps its own clone of the value together with
+//! the pointer's version. A read compares that version with the current one, a single atomic
+//! load, and hands out the thread's clone without touching the lock if nothing has been written
+//! since. Only the first read on each thread after a write takes the read lock and clones the
+//! value again, so writes get more expensive the more threads read.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Weak,
+};
+
+use crate::arc_rwlock::{
+    SharedPtr,
+    WriteGuard,
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Snapshot {
+    // Dead once every clone of the pointer has been dropped, so the snapshot can be discarded.
+    alive: Weak<()>,
+    version: u64,
+    value: Rc<dyn Any>,
+}
+
+thread_local! {
+    static SNAPSHOTS: RefCell<HashMap<u64, Snapshot>> = RefCell::new(HashMap::new());
+}
+
+/// A pointer whose reads are served from a per-thread clone of the value. Clones share the value
+/// and the per-thread snapshots.
+pub struct SharedReadMostly<T> {
+    value: SharedPtr<T>,
+    id: u64,
+    alive: Arc<()>,
+}
+
+impl<T: Clone + 'static> SharedReadMostly<T> {
+    pub fn new(init: T) -> Self {
+        SharedReadMostly {
+            value: SharedPtr::new(init),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(()),
+        }
+    }
+
+    /// Returns this thread's snapshot of the value, first refreshing it if there has been a
+    /// write since it was taken.
+    pub fn read(&self) -> Rc<T> {
+        let version = self.value.version();
+        let cached = SNAPSHOTS.with(|snapshots| {
+            let snapshots = snapshots.borrow();
+            let snapshot = snapshots.get(&self.id).filter(|s| s.version == version)?;
+            Some(snapshot.value.clone())
+        });
+        if let Some(value) = cached {
+            return value.downcast().expect("snapshot ids are unique per pointer");
+        }
+
+        // The version can't change while the read lock is held, so it matches the clone.
+        let (version, value) = {
+            let guard = self.value.read();
+            (self.value.version(), Rc::new(T::clone(&guard)))
+        };
+        SNAPSHOTS.with(|snapshots| {
+            let mut snapshots = snapshots.borrow_mut();
+            snapshots.retain(|_, snapshot| snapshot.alive.strong_count() > 0);
+            let snapshot = Snapshot {
+                alive: Arc::downgrade(&self.alive),
+                version,
+                value: value.clone(),
+            };
+            snapshots.insert(self.id, snapshot);
+        });
+        value
+    }
+
+    /// Write-locks the value. Once the guard is released, every thread's next `read()` clones
+    /// the new value.
+    #[track_caller]
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        self.value.write()
+    }
+}
+
+impl<T> Clone for SharedReadMostly<T> {
+    fn clone(&self) -> Self {
+        SharedReadMostly {
+            value: self.value.clone(),
+            id: self.id,
+            alive: self.alive.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for SharedReadMostly<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("SharedReadMostly").field(&*self.value.read()).finish()
+    }
+}
+
+#[test]
+fn test_reads_reuse_snapshot_until_write() {
+    let flags = SharedReadMostly::new(vec![1u32]);
+    let first = flags.read();
+    assert!(Rc::ptr_eq(&first, &flags.clone().read()));
+
+    let other = flags.clone();
+    let (before, after) = std::thread::spawn(move || {
+        let before = other.read().to_vec();
+        other.write().push(2);
+        (before, other.read().to_vec())
+    })
+    .join()
+    .unwrap();
+    assert_eq!((before, after), (vec![1], vec![1, 2]));
+
+    let second = flags.read();
+    assert!(!Rc::ptr_eq(&first, &second));
+    assert_eq!(*second, [1, 2]);
+    assert_eq!(*first, [1]);
+
+    drop(flags);
+    let _ = SharedReadMostly::new(0u8).read();
+    SNAPSHOTS.with(|snapshots| assert_eq!(snapshots.borrow().len(), 1));
+}
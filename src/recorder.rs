@@ -0,0 +1,125 @@
+//! Ring buffer of recent states for post-mortem debugging.
+//!
+//! When a value ends up corrupted by some interleaving of writers, the final state rarely says
+//! how it got there. A `SnapshotRecorder` keeps the last few states of a pointer, rendered with
+//! `Debug` as each write guard is released, and can print them on demand or when the process
+//! panics.
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io;
+use std::sync::{
+    Arc,
+    Weak,
+};
+use std::time::SystemTime;
+
+use crate::deps::parking_lot::Mutex;
+
+/// A rendered state of the pointer.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub timestamp: SystemTime,
+    pub state: String,
+}
+
+struct Ring {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+}
+
+/// Handle to the snapshots recorded for one pointer. Recording stops once every handle is
+/// dropped.
+#[derive(Clone)]
+pub struct SnapshotRecorder(Arc<Mutex<Ring>>);
+
+impl SnapshotRecorder {
+    pub(crate) fn new(capacity: usize) -> Self {
+        SnapshotRecorder(Arc::new(Mutex::new(Ring {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        })))
+    }
+
+    pub(crate) fn sink(&self) -> SnapshotSink {
+        SnapshotSink(Arc::downgrade(&self.0))
+    }
+
+    /// The recorded snapshots, oldest first.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.0.lock().snapshots.iter().cloned().collect()
+    }
+
+    /// Writes every recorded snapshot to `out`, oldest first.
+    pub fn dump(&self, out: &mut impl io::Write) -> io::Result<()> {
+        let snapshots = self.snapshots();
+        writeln!(out, "last {} recorded states:", snapshots.len())?;
+        for (index, snapshot) in snapshots.iter().enumerate() {
+            writeln!(out, "[{}] {:?}: {}", index, snapshot.timestamp, snapshot.state)?;
+        }
+        Ok(())
+    }
+
+    /// Installs a panic hook that dumps the snapshots to stderr before running the previously
+    /// installed hook. The hook keeps the recorder alive for the rest of the process.
+    pub fn dump_on_panic(&self) {
+        let recorder = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = recorder.dump(&mut io::stderr().lock());
+            previous(info);
+        }));
+    }
+}
+
+impl std::fmt::Debug for SnapshotRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SnapshotRecorder")
+            .field("snapshots", &self.0.lock().snapshots)
+            .finish()
+    }
+}
+
+/// The writing side of a recorder, held by the pointer's observer list.
+pub(crate) struct SnapshotSink(Weak<Mutex<Ring>>);
+
+impl SnapshotSink {
+    /// Records `value`, returning false once the recorder is gone.
+    pub(crate) fn record<T: Debug + ?Sized>(&self, value: &T) -> bool {
+        let ring = match self.0.upgrade() {
+            Some(ring) => ring,
+            None => return false,
+        };
+        let snapshot = Snapshot {
+            timestamp: SystemTime::now(),
+            state: format!("{:#?}", value),
+        };
+        let mut ring = ring.lock();
+        if ring.capacity > 0 {
+            if ring.snapshots.len() == ring.capacity {
+                ring.snapshots.pop_front();
+            }
+            ring.snapshots.push_back(snapshot);
+        }
+        true
+    }
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_recorder_keeps_last_states() {
+    let ptr = crate::arc_rwlock::SharedPtr::new(0u32);
+    let recorder = ptr.record_snapshots(3);
+    for i in 1..=5 {
+        *ptr.write() = i;
+    }
+
+    let states: Vec<_> = recorder.snapshots().into_iter().map(|s| s.state).collect();
+    assert_eq!(states, ["3", "4", "5"]);
+
+    let mut out = Vec::new();
+    recorder.dump(&mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().starts_with("last 3 recorded states:"));
+
+    drop(recorder);
+    *ptr.write() = 6;
+}
@@ -0,0 +1,184 @@
+//! Pointers whose value is reloaded periodically, for feature flags, resolved addresses and other
+//! data that goes stale.
+//!
+//! `SharedRefresh::spawn` reloads on a background thread of its own. To drive the reloads from an
+//! existing scheduler instead, create the pointer with `new` and call `refresh` from there. Either
+//! way a reload runs without holding the lock and only takes the write lock to swap the new value
+//! in, so readers never wait for the reload itself.
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::arc_rwlock::{
+    ReadGuard,
+    SharedPtr,
+};
+use crate::deps::parking_lot::Mutex;
+
+type Reload<T, E> = Box<dyn FnMut() -> Result<T, E> + Send>;
+
+struct Refresher<T, E> {
+    value: SharedPtr<T>,
+    reload: Mutex<Reload<T, E>>,
+    error: Mutex<Option<E>>,
+}
+
+impl<T, E> Refresher<T, E> {
+    fn refresh(&self) -> Result<(), E> {
+        let value = (self.reload.lock())()?;
+        let stale = std::mem::replace(&mut *self.value.write(), value);
+        drop(stale);
+        Ok(())
+    }
+}
+
+struct Worker {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A pointer that replaces its value with the result of a reload closure. Clones share the value
+/// and the background thread, which stops once every clone has been dropped.
+pub struct SharedRefresh<T, E> {
+    refresher: Arc<Refresher<T, E>>,
+    worker: Option<Arc<Worker>>,
+}
+
+impl<T, E> SharedRefresh<T, E>
+where
+    T: Send + Sync + 'static,
+    E: Send + 'static,
+{
+    /// Creates a pointer holding `init` that only reloads when `refresh` is called.
+    pub fn new(init: T, reload: impl FnMut() -> Result<T, E> + Send + 'static) -> Self {
+        let refresher = Refresher {
+            value: SharedPtr::new(init),
+            reload: Mutex::new(Box::new(reload)),
+            error: Mutex::new(None),
+        };
+        SharedRefresh {
+            refresher: Arc::new(refresher),
+            worker: None,
+        }
+    }
+
+    /// Loads the initial value with `reload`, failing if that does, and then reloads every
+    /// `interval` on a background thread. A failed background reload keeps the previous value
+    /// and leaves the error for `take_error`.
+    pub fn spawn(
+        interval: Duration,
+        mut reload: impl FnMut() -> Result<T, E> + Send + 'static,
+    ) -> Result<Self, E> {
+        let mut refresh = SharedRefresh::new(reload()?, reload);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let refresher = refresh.refresher.clone();
+        let thread = std::thread::Builder::new()
+            .name("shared-ptrs-refresh".into())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if let Err(error) = refresher.refresh() {
+                        *refresher.error.lock() = Some(error);
+                    }
+                }
+            })
+            .expect("failed to spawn the refresh thread");
+        refresh.worker = Some(Arc::new(Worker {
+            stop: Some(stop),
+            thread: Some(thread),
+        }));
+        Ok(refresh)
+    }
+}
+
+impl<T, E> SharedRefresh<T, E> {
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        self.refresher.value.read()
+    }
+
+    /// Reloads the value now. On error the previous value is kept.
+    pub fn refresh(&self) -> Result<(), E> {
+        self.refresher.refresh()
+    }
+
+    /// Returns the error from the most recent failed background reload, if any, and clears it.
+    pub fn take_error(&self) -> Option<E> {
+        self.refresher.error.lock().take()
+    }
+}
+
+impl<T, E> Clone for SharedRefresh<T, E> {
+    fn clone(&self) -> Self {
+        SharedRefresh {
+            refresher: self.refresher.clone(),
+            worker: self.worker.clone(),
+        }
+    }
+}
+
+impl<T, E> std::fmt::Debug for SharedRefresh<T, E>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedRefresh")
+            .field("value", &*self.read())
+            .field("background", &self.worker.is_some())
+            .finish()
+    }
+}
+
+#[test]
+fn test_refresh() {
+    let mut version = 0u32;
+    let flags = SharedRefresh::new(0u32, move || {
+        version += 1;
+        if version == 2 {
+            Err("unreachable")
+        } else {
+            Ok(version)
+        }
+    });
+    flags.refresh().unwrap();
+    assert_eq!(*flags.read(), 1);
+    assert_eq!(flags.refresh(), Err("unreachable"));
+    assert_eq!(*flags.clone().read(), 1);
+    flags.refresh().unwrap();
+    assert_eq!(*flags.read(), 3);
+}
+
+#[test]
+fn test_spawned_refresh() {
+    use std::sync::atomic::{
+        AtomicU32,
+        Ordering,
+    };
+
+    let reloads = Arc::new(AtomicU32::new(0));
+    let counted = reloads.clone();
+    let reload = move || match counted.fetch_add(1, Ordering::SeqCst) {
+        1 => Err("timeout"),
+        reloads => Ok(reloads),
+    };
+    let config = SharedRefresh::spawn(Duration::from_millis(5), reload).unwrap();
+    assert_eq!(*config.read(), 0);
+
+    while *config.read() < 3 {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(config.take_error(), Some("timeout"));
+    assert_eq!(config.take_error(), None);
+
+    drop(config);
+    let stopped = reloads.load(Ordering::SeqCst);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(reloads.load(Ordering::SeqCst), stopped);
+}
@@ -0,0 +1,284 @@
+//! Opt-in registry of named locks, for finding out what a stuck process is waiting on.
+//!
+//! Once `enable()` is called, pointers created with `new_named` register themselves until they
+//! are dropped. `locks()` reports the state of every registered lock and, for locks held through
+//! `write()`, where and for how long they have been held. `dump_locks()` prints the same report,
+//! e.g. from a `SIGUSR1` handler thread.
+//!
+//! `rc_refcell` pointers can only be inspected from the thread that created them, so they are
+//! only reported by calls made on that thread.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::panic::Location;
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    Ordering,
+};
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::deps::parking_lot::{
+    const_mutex,
+    Mutex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Unlocked,
+    /// Held by one or more readers.
+    Shared,
+    /// Held by a writer, or by a reader of an exclusive lock such as a mutex.
+    Exclusive,
+}
+
+/// The `write()` currently holding a lock.
+#[derive(Debug, Clone)]
+pub struct Holder {
+    pub location: &'static Location<'static>,
+    pub thread_name: Option<String>,
+    pub since: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct LockReport {
+    pub name: String,
+    pub state: LockState,
+    /// Only known for locks held through `write()`.
+    pub holder: Option<Holder>,
+}
+
+impl LockReport {
+    /// How long the lock has been held by `holder`.
+    pub fn held_for(&self) -> Option<Duration> {
+        self.holder.as_ref().map(|holder| holder.since.elapsed())
+    }
+}
+
+/// Reads the state of a registered lock without blocking on it.
+pub(crate) struct Probe {
+    lock: *const (),
+    state: unsafe fn(*const ()) -> LockState,
+}
+
+// SAFETY: `Probe::new` requires `state` to be safe to call from any thread, unless the probe is
+// registered with `Registration::new_local`, which never lets it leave its thread.
+unsafe impl Send for Probe {}
+
+impl Probe {
+    /// # Safety
+    ///
+    /// `state(lock)` must be safe to call until the registration the probe is given to is
+    /// dropped, from any thread unless the registration is local.
+    pub(crate) unsafe fn new(lock: *const (), state: unsafe fn(*const ()) -> LockState) -> Self {
+        Probe { lock, state }
+    }
+}
+
+struct Entry {
+    name: String,
+    holder: Arc<Mutex<Option<Holder>>>,
+    probe: Probe,
+}
+
+impl Entry {
+    fn report(&self) -> LockReport {
+        LockReport {
+            name: self.name.clone(),
+            // SAFETY: entries are removed when their registration is dropped.
+            state: unsafe { (self.probe.state)(self.probe.lock) },
+            holder: self.holder.lock().clone(),
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static LOCKS: Mutex<BTreeMap<u64, Entry>> = const_mutex(BTreeMap::new());
+
+thread_local! {
+    static LOCAL_LOCKS: RefCell<BTreeMap<u64, Entry>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Starts registering named pointers created from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops registering new pointers. Pointers already registered stay until dropped.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// The state of every registered lock, in order of creation.
+pub fn locks() -> Vec<LockReport> {
+    let mut reports: Vec<_> =
+        LOCKS.lock().iter().map(|(id, entry)| (*id, entry.report())).collect();
+    LOCAL_LOCKS.with(|local| {
+        reports.extend(local.borrow().iter().map(|(id, entry)| (*id, entry.report())));
+    });
+    reports.sort_by_key(|(id, _)| *id);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Writes one line per registered lock to `out`.
+pub fn dump_locks(out: &mut impl io::Write) -> io::Result<()> {
+    let locks = locks();
+    writeln!(out, "{} registered locks:", locks.len())?;
+    for lock in locks {
+        write!(out, "{}: {:?}", lock.name, lock.state)?;
+        if let Some(holder) = &lock.holder {
+            write!(
+                out,
+                " by {} on {} for {:?}",
+                holder.location,
+                holder.thread_name.as_deref().unwrap_or("<unnamed>"),
+                holder.since.elapsed()
+            )?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Registration of one lock, removed when dropped.
+pub(crate) struct Registration {
+    id: u64,
+    local: bool,
+    holder: Arc<Mutex<Option<Holder>>>,
+}
+
+impl Registration {
+    pub(crate) fn is_enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    #[cfg(any(feature = "mutex", feature = "rwlock"))]
+    pub(crate) fn new(name: &str, probe: Probe) -> Self {
+        let registration = Registration::unregistered(false);
+        let entry = registration.entry(name, probe);
+        LOCKS.lock().insert(registration.id, entry);
+        registration
+    }
+
+    /// Registers a lock that can only be probed from the current thread.
+    #[cfg(feature = "rc")]
+    pub(crate) fn new_local(name: &str, probe: Probe) -> Self {
+        let registration = Registration::unregistered(true);
+        let entry = registration.entry(name, probe);
+        LOCAL_LOCKS.with(|local| local.borrow_mut().insert(registration.id, entry));
+        registration
+    }
+
+    fn unregistered(local: bool) -> Self {
+        Registration {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            local,
+            holder: Default::default(),
+        }
+    }
+
+    fn entry(&self, name: &str, probe: Probe) -> Entry {
+        Entry {
+            name: name.to_owned(),
+            holder: self.holder.clone(),
+            probe,
+        }
+    }
+
+    pub(crate) fn hold(&self, location: &'static Location<'static>) {
+        *self.holder.lock() = Some(Holder {
+            location,
+            thread_name: std::thread::current().name().map(String::from),
+            since: Instant::now(),
+        });
+    }
+
+    pub(crate) fn release(&self) {
+        *self.holder.lock() = None;
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if self.local {
+            // Ignore the error if the thread-local table is already gone at thread exit.
+            let _ = LOCAL_LOCKS.try_with(|local| local.borrow_mut().remove(&self.id));
+        } else {
+            LOCKS.lock().remove(&self.id);
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "rc", feature = "rwlock"))]
+fn test_dump_locks() {
+    use crate::arc_rwlock::SharedPtr;
+
+    enable();
+    let scene = SharedPtr::new_named("registry.test.scene", 0u32);
+    let _unnamed = SharedPtr::new(0u32);
+    let local = crate::rc_refcell::SharedPtr::new_named("registry.test.local", 0u32);
+    disable();
+
+    let report = |name: &str| locks().into_iter().find(|lock| lock.name == name);
+    assert_eq!(report("registry.test.scene").unwrap().state, LockState::Unlocked);
+
+    let guard = scene.write();
+    let held = report("registry.test.scene").unwrap();
+    assert_eq!(held.state, LockState::Exclusive);
+    assert_eq!(held.holder.unwrap().location.file(), file!());
+    drop(guard);
+
+    let reader = scene.read();
+    let shared = report("registry.test.scene").unwrap();
+    assert_eq!(shared.state, LockState::Shared);
+    assert!(shared.holder.is_none());
+    drop(reader);
+
+    let borrowed = local.read();
+    assert_eq!(report("registry.test.local").unwrap().state, LockState::Shared);
+    drop(borrowed);
+
+    let mut out = Vec::new();
+    dump_locks(&mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().contains("registry.test.scene: Unlocked"));
+
+    drop(scene);
+    drop(local);
+    assert!(report("registry.test.scene").is_none());
+    assert!(report("registry.test.local").is_none());
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_locks_during_registration() {
+    use std::sync::atomic::AtomicBool;
+
+    use crate::arc_rwlock::SharedPtr;
+
+    enable();
+    let done = Arc::new(AtomicBool::new(false));
+    let prober = {
+        let done = done.clone();
+        std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                // Pointers only appear once their lock has been initialized.
+                for lock in locks() {
+                    if lock.name == "registry.test.concurrent" {
+                        assert_eq!(lock.state, LockState::Unlocked);
+                    }
+                }
+            }
+        })
+    };
+    for _ in 0..2000 {
+        drop(SharedPtr::new_named("registry.test.concurrent", [0u64; 8]));
+    }
+    done.store(true, Ordering::Relaxed);
+    prober.join().unwrap();
+}
@@ -0,0 +1,104 @@
+//! Pointers whose owner can cut off every other handle.
+//!
+//! Each backend module has a `Revocable<T>`, created together with the `RevokeHandle<T>` that
+//! owns it. Revoking waits for guards that are currently held, then takes the value out, so
+//! every later access through any clone fails with `Revoked` instead of waiting for the clones
+//! to be dropped.
+
+/// Access through a `Revocable` whose owner has revoked it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revoked;
+
+impl std::fmt::Display for Revoked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("access to the shared value was revoked")
+    }
+}
+
+impl std::error::Error for Revoked {}
+
+macro_rules! define_revocable {
+    () => {
+        /// A handle whose access can be revoked by its `RevokeHandle`. See the `revocable`
+        /// module docs.
+        pub struct Revocable<T>(SharedPtr<Option<T>>);
+
+        /// The owner of a `Revocable`'s value.
+        pub struct RevokeHandle<T>(SharedPtr<Option<T>>);
+
+        impl<T> Revocable<T> {
+            pub fn new(init: T) -> (Revocable<T>, RevokeHandle<T>) {
+                let ptr = SharedPtr::new(Some(init));
+                (Revocable(ptr.clone()), RevokeHandle(ptr))
+            }
+
+            pub fn read(&self) -> Result<FieldRef<'_, Option<T>, T>, crate::revocable::Revoked> {
+                let slot = self.0.read();
+                if slot.is_none() {
+                    return Err(crate::revocable::Revoked);
+                }
+                Ok(crate::field::FieldGuard::new(slot).map(|v| v.as_ref().unwrap()))
+            }
+
+            #[track_caller]
+            pub fn write(
+                &self,
+            ) -> Result<FieldRefMut<'_, Option<T>, T>, crate::revocable::Revoked> {
+                let slot = self.0.write();
+                if slot.is_none() {
+                    return Err(crate::revocable::Revoked);
+                }
+                Ok(crate::field::FieldGuardMut::new(slot).map(|v| v.as_mut().unwrap()))
+            }
+
+            pub fn is_revoked(&self) -> bool {
+                self.0.read().is_none()
+            }
+        }
+
+        impl<T> RevokeHandle<T> {
+            /// Waits for outstanding guards to be released, then revokes every `Revocable` and
+            /// returns the value.
+            pub fn revoke(self) -> T {
+                self.0.write().take().unwrap()
+            }
+        }
+
+        impl<T> Clone for Revocable<T> {
+            fn clone(&self) -> Self {
+                Revocable(self.0.clone())
+            }
+        }
+
+        impl<T> std::fmt::Debug for Revocable<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match &*self.0.read() {
+                    Some(value) => f.debug_tuple("Revocable").field(value).finish(),
+                    None => f.write_str("Revocable(<revoked>)"),
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for RevokeHandle<T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("RevokeHandle")
+            }
+        }
+
+        #[test]
+        fn test_revocable() {
+            let (plugin, owner) = Revocable::new(vec![1u32]);
+            let clone = plugin.clone();
+            plugin.write().unwrap().push(2);
+            assert_eq!(*clone.read().unwrap(), [1, 2]);
+
+            assert_eq!(owner.revoke(), [1, 2]);
+            assert!(plugin.is_revoked());
+            assert_eq!(clone.read().err(), Some(crate::revocable::Revoked));
+            assert!(plugin.write().is_err());
+        }
+    };
+}
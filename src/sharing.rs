@@ -0,0 +1,188 @@
+//! Serde support that preserves pointer sharing.
+//!
+//! By default a `SharedPtr` serializes as its value, so a structure holding N clones of one
+//! pointer writes the value N times and deserializes into N independent pointers. Pointers that
+//! should keep their identity are stored as `Shared<SharedPtr<T>>` instead. Wrapping the root
+//! of the (de)serialization in `PreserveSharing` switches every such pointer inside it to an
+//! `(id, value)` pair: the first occurrence of a pointer carries `Some(value)`, later ones
+//! `None`, and deserializing hands out clones of the first pointer for every repeated id.
+//! Outside `PreserveSharing`, `Shared` (de)serializes exactly like the pointer it wraps.
+//!
+//! ```ignore
+//! struct Graph {
+//!     nodes: Vec<Shared<SharedPtr<Node>>>,
+//! }
+//!
+//! let json = serde_json::to_string(&PreserveSharing(&graph))?;
+//! let PreserveSharing(graph): PreserveSharing<Graph> = serde_json::from_str(&json)?;
+//! ```
+//!
+//! Deserializing a `Shared` pointer needs `T: 'static`. Cycles serialize fine but cannot be
+//! deserialized, since a pointer is only known once its value has been read in full.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::deps::serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+thread_local! {
+    static SERIALIZING: RefCell<Option<HashMap<*const (), u64>>> = const { RefCell::new(None) };
+    static DESERIALIZING: RefCell<Option<HashMap<u64, Box<dyn Any>>>> =
+        const { RefCell::new(None) };
+}
+
+/// Wrapper that (de)serializes its contents with pointer sharing preserved. See the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PreserveSharing<T>(pub T);
+
+/// A pointer that keeps its identity inside `PreserveSharing`. See the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Shared<P>(pub P);
+
+impl<T: Serialize> Serialize for PreserveSharing<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let previous = SERIALIZING.with(|ids| ids.replace(Some(HashMap::new())));
+        let _restore = Restore(Some(|| SERIALIZING.with(|ids| *ids.borrow_mut() = previous)));
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PreserveSharing<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let previous = DESERIALIZING.with(|table| table.replace(Some(HashMap::new())));
+        let _restore =
+            Restore(Some(|| DESERIALIZING.with(|table| *table.borrow_mut() = previous)));
+        T::deserialize(deserializer).map(PreserveSharing)
+    }
+}
+
+/// Runs a closure on drop, so sessions are torn down even if (de)serialization panics.
+struct Restore<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Drop for Restore<F> {
+    fn drop(&mut self) {
+        if let Some(restore) = self.0.take() {
+            restore()
+        }
+    }
+}
+
+/// If a sharing session is active, returns the id of the pointer at `address` if it has been
+/// serialized already.
+pub(crate) fn serialized_id(address: *const ()) -> Option<Option<u64>> {
+    SERIALIZING.with(|ids| ids.borrow().as_ref().map(|ids| ids.get(&address).copied()))
+}
+
+/// Assigns the next id to the pointer at `address`, which is being serialized in full.
+pub(crate) fn register(address: *const ()) -> u64 {
+    SERIALIZING.with(|ids| {
+        let mut ids = ids.borrow_mut();
+        let ids = ids.as_mut().expect("no sharing session");
+        let id = ids.len() as u64;
+        ids.insert(address, id);
+        id
+    })
+}
+
+pub(crate) fn is_deserializing() -> bool {
+    DESERIALIZING.with(|table| table.borrow().is_some())
+}
+
+pub(crate) fn insert<P: Any>(id: u64, pointer: P) {
+    DESERIALIZING.with(|table| {
+        if let Some(table) = table.borrow_mut().as_mut() {
+            table.insert(id, Box::new(pointer));
+        }
+    })
+}
+
+/// Returns a clone of the pointer deserialized under `id`, if there is one of type `P`.
+pub(crate) fn lookup<P: Any + Clone>(id: u64) -> Option<P> {
+    DESERIALIZING.with(|table| {
+        table
+            .borrow()
+            .as_ref()
+            .and_then(|table| table.get(&id))
+            .and_then(|pointer| pointer.downcast_ref::<P>())
+            .cloned()
+    })
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_preserve_sharing_round_trip() {
+    use crate::arc_rwlock::SharedPtr;
+    use crate::test_value::{
+        from_value,
+        to_value,
+    };
+
+    let a = Shared(SharedPtr::new(1u32));
+    let b = Shared(SharedPtr::new(2u32));
+    let graph = (a.clone(), vec![a.clone(), b.clone(), a], b);
+    type Graph = (Shared<SharedPtr<u32>>, Vec<Shared<SharedPtr<u32>>>, Shared<SharedPtr<u32>>);
+
+    let plain: Graph = from_value(to_value(&graph).unwrap()).unwrap();
+    *plain.0 .0.write() = 10;
+    assert_eq!(*plain.1[0].0.read(), 1);
+
+    let value = to_value(&PreserveSharing(&graph)).unwrap();
+    let PreserveSharing(shared): PreserveSharing<Graph> = from_value(value).unwrap();
+    *shared.0 .0.write() = 10;
+    *shared.2 .0.write() = 20;
+    assert_eq!(*shared.1[0].0.read(), 10);
+    assert_eq!(*shared.1[1].0.read(), 20);
+    assert_eq!(*shared.1[2].0.read(), 10);
+
+    // Bare pointers ignore the session.
+    let bare: PreserveSharing<(SharedPtr<u32>, SharedPtr<u32>)> =
+        from_value(to_value(&PreserveSharing((&graph.0 .0, &graph.0 .0))).unwrap()).unwrap();
+    *bare.0 .0.write() = 30;
+    assert_eq!(*bare.0 .1.read(), 1);
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_preserve_sharing_with_placeholders() {
+    use crate::arc_rwlock::SharedPtr;
+    use crate::test_value::{
+        from_value,
+        to_value,
+        Value,
+    };
+    use crate::try_serialize::{
+        OnContention,
+        TrySerialize,
+    };
+
+    let free = Shared(SharedPtr::new(1u32));
+    let held = Shared(SharedPtr::new(2u32));
+    let graph = (held.clone(), free.clone(), held.clone(), free);
+    let guard = held.0.write();
+    let snapshot = TrySerialize::new(&graph, OnContention::Placeholder);
+    let value = to_value(&PreserveSharing(snapshot)).unwrap();
+    drop(guard);
+
+    let pair = |id, value: Option<u64>| {
+        let value = value.map_or(Value::None, |v| Value::Some(Box::new(Value::U64(v))));
+        Value::Seq(vec![Value::U64(id), value])
+    };
+    let expected = vec![Value::Unit, pair(0, Some(1)), Value::Unit, pair(0, None)];
+    assert_eq!(value, Value::Seq(expected));
+
+    type Graph = (
+        Option<Shared<SharedPtr<u32>>>,
+        Shared<SharedPtr<u32>>,
+        Option<Shared<SharedPtr<u32>>>,
+        Shared<SharedPtr<u32>>,
+    );
+    let PreserveSharing(restored): PreserveSharing<Graph> = from_value(value).unwrap();
+    assert!(restored.0.is_none() && restored.2.is_none());
+    *restored.1 .0.write() = 10;
+    assert_eq!(*restored.3 .0.read(), 10);
+}
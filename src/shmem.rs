@@ -0,0 +1,405 @@
+//! A pointer to a value in a named POSIX shared memory segment, for sharing state between
+//! processes on one host.
+//!
+//! `SharedPtr::open` creates the segment, or attaches to it if another process got there first.
+//! The segment holds a process-shared mutex next to the value, so `read()` and `write()` exclude
+//! each other across every process that has it open. The segment outlives the processes using it
+//! until it is removed with `unlink`.
+//!
+//! Each process maps the segment at its own address, so the value must not contain pointers or
+//! anything else that is only meaningful in one process. That is what the `Shareable` bound
+//! asserts.
+//!
+//! On Linux the mutex is robust: if a process dies while holding a guard, the next one to lock
+//! the segment gets the value as that process left it, possibly half written. Elsewhere the mutex
+//! stays locked for everyone else. Locking a segment again on a thread that already holds one of
+//! its guards panics rather than deadlocking.
+use std::ffi::CString;
+use std::io;
+use std::sync::atomic::{
+    AtomicU32,
+    Ordering,
+};
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::deps::libc;
+
+/// Types whose bytes mean the same thing in every process that maps them.
+///
+/// # Safety
+///
+/// The type must not contain pointers, references, file descriptors or other process-local
+/// handles, must be valid for any bytes another process may have written, and must be laid out
+/// identically in every process (`#[repr(C)]` for user types).
+pub unsafe trait Shareable: Copy + 'static {}
+
+macro_rules! impl_shareable {
+    ($($ty:ty),*) => {
+        $(unsafe impl Shareable for $ty {})*
+    };
+}
+
+impl_shareable!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Shareable, const N: usize> Shareable for [T; N] {}
+
+const INITIALIZING: u32 = 1;
+const READY: u32 = 2;
+
+/// How long an attaching process waits for the creator to finish initializing the segment.
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[repr(C)]
+struct Segment<T> {
+    state: AtomicU32,
+    size: u64,
+    lock: libc::pthread_mutex_t,
+    value: T,
+}
+
+struct Mapping<T> {
+    segment: *mut Segment<T>,
+}
+
+// SAFETY: the segment is only touched through the process-shared mutex or atomics, and `T` has
+// no thread affinity since it is plain data.
+unsafe impl<T: Shareable + Send> Send for Mapping<T> {}
+unsafe impl<T: Shareable + Send> Sync for Mapping<T> {}
+
+impl<T> Drop for Mapping<T> {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `mmap` with this length and is not used again.
+        unsafe {
+            libc::munmap(self.segment.cast(), std::mem::size_of::<Segment<T>>());
+        }
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn check_pthread(result: libc::c_int) -> io::Result<()> {
+    match result {
+        0 => Ok(()),
+        code => Err(io::Error::from_raw_os_error(code)),
+    }
+}
+
+fn segment_name(name: &str) -> io::Result<CString> {
+    let name = if name.starts_with('/') { name.to_owned() } else { format!("/{}", name) };
+    CString::new(name).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+}
+
+/// Removes the segment `name`. Processes that already have it open keep using it; the next
+/// `open` creates a fresh one.
+pub fn unlink(name: &str) -> io::Result<()> {
+    let name = segment_name(name)?;
+    // SAFETY: `name` is a valid C string.
+    check(unsafe { libc::shm_unlink(name.as_ptr()) }).map(drop)
+}
+
+/// A handle to a value in a shared memory segment. Clones share the mapping.
+pub struct SharedPtr<T: Shareable>(Arc<Mapping<T>>);
+
+impl<T: Shareable> SharedPtr<T> {
+    /// Attaches to the segment `name`, creating it with `init` if it doesn't exist. A leading `/`
+    /// is added to `name` if missing.
+    ///
+    /// Fails with `InvalidData` if the segment was created for a value of a different size. If
+    /// creating the segment fails part way, it is unlinked again so the next `open` starts over.
+    /// A creator that dies before finishing leaves a segment that never becomes ready: attaching
+    /// to it fails with `TimedOut` after a few seconds, and it has to be removed with `unlink`
+    /// before it can be opened again.
+    pub fn open(name: &str, init: T) -> io::Result<Self> {
+        let name = segment_name(name)?;
+        let size = std::mem::size_of::<Segment<T>>();
+        // SAFETY: `name` is a valid C string; the descriptor is closed before returning.
+        let (fd, created) = unsafe {
+            let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL;
+            match check(libc::shm_open(name.as_ptr(), flags, 0o600)) {
+                Ok(fd) => (fd, true),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    (check(libc::shm_open(name.as_ptr(), libc::O_RDWR, 0o600))?, false)
+                }
+                Err(error) => return Err(error),
+            }
+        };
+        let mapping = Self::map(fd, size, created);
+        // SAFETY: `fd` is open and no longer needed once mapped.
+        unsafe { libc::close(fd) };
+        let mapping = mapping.and_then(|mapping| {
+            if created {
+                // SAFETY: the segment was just created, so nothing else uses it until `state` is
+                // published as `READY`.
+                unsafe { Self::initialize(mapping.segment, init)? };
+            } else {
+                Self::await_ready(&mapping)?;
+            }
+            Ok(mapping)
+        });
+        if created && mapping.is_err() {
+            // Processes that attached in the meantime time out waiting for `READY`.
+            // SAFETY: `name` is a valid C string.
+            unsafe { libc::shm_unlink(name.as_ptr()) };
+        }
+        Ok(SharedPtr(Arc::new(mapping?)))
+    }
+
+    fn map(fd: libc::c_int, size: usize, created: bool) -> io::Result<Mapping<T>> {
+        // SAFETY: `fd` is an open shared memory descriptor, and `fstat` only writes to `stat`.
+        unsafe {
+            if created {
+                check(libc::ftruncate(fd, size as libc::off_t))?;
+            } else {
+                // The creator may not have sized the segment yet.
+                let deadline = Instant::now() + INIT_TIMEOUT;
+                loop {
+                    let mut stat = std::mem::zeroed::<libc::stat>();
+                    check(libc::fstat(fd, &mut stat))?;
+                    if stat.st_size as usize >= size {
+                        break;
+                    }
+                    if Instant::now() > deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "shared memory segment is smaller than the value",
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            let address = libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            if address == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Mapping {
+                segment: address.cast(),
+            })
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `segment` must be a fresh, zeroed mapping that no other process uses yet.
+    unsafe fn initialize(segment: *mut Segment<T>, init: T) -> io::Result<()> {
+        (*segment).state.store(INITIALIZING, Ordering::Relaxed);
+        std::ptr::addr_of_mut!((*segment).size).write(std::mem::size_of::<T>() as u64);
+        std::ptr::addr_of_mut!((*segment).value).write(init);
+
+        let mut attr = std::mem::MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+        check_pthread(libc::pthread_mutexattr_init(attr.as_mut_ptr()))?;
+        let result = check_pthread(libc::pthread_mutexattr_setpshared(
+            attr.as_mut_ptr(),
+            libc::PTHREAD_PROCESS_SHARED,
+        ))
+        .and_then(|()| {
+            check_pthread(libc::pthread_mutexattr_settype(
+                attr.as_mut_ptr(),
+                libc::PTHREAD_MUTEX_ERRORCHECK,
+            ))
+        })
+        .and_then(|()| {
+            #[cfg(target_os = "linux")]
+            check_pthread(libc::pthread_mutexattr_setrobust(
+                attr.as_mut_ptr(),
+                libc::PTHREAD_MUTEX_ROBUST,
+            ))?;
+            Ok(())
+        })
+        .and_then(|()| {
+            check_pthread(libc::pthread_mutex_init(
+                std::ptr::addr_of_mut!((*segment).lock),
+                attr.as_ptr(),
+            ))
+        });
+        libc::pthread_mutexattr_destroy(attr.as_mut_ptr());
+        result?;
+
+        (*segment).state.store(READY, Ordering::Release);
+        Ok(())
+    }
+
+    fn await_ready(mapping: &Mapping<T>) -> io::Result<()> {
+        // SAFETY: the mapping is at least as large as the segment, and `state` is atomic.
+        let segment = unsafe { &*mapping.segment };
+        let deadline = Instant::now() + INIT_TIMEOUT;
+        while segment.state.load(Ordering::Acquire) != READY {
+            if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "shared memory segment was never initialized; unlink it if its creator died",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if segment.size != std::mem::size_of::<T>() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "shared memory segment holds a {} byte value, not {} bytes",
+                    segment.size,
+                    std::mem::size_of::<T>()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> Guard<'_, T> {
+        let segment = self.0.segment;
+        // SAFETY: the mutex was initialized before the segment was published as ready.
+        let lock = unsafe { std::ptr::addr_of_mut!((*segment).lock) };
+        // SAFETY: as above.
+        match unsafe { libc::pthread_mutex_lock(lock) } {
+            0 => {}
+            // The previous owner died holding the lock. Its write may be incomplete, but
+            // `Shareable` values are valid for any bytes, so the value is handed out as it is.
+            #[cfg(target_os = "linux")]
+            libc::EOWNERDEAD => {
+                // SAFETY: this thread now owns the mutex.
+                check_pthread(unsafe { libc::pthread_mutex_consistent(lock) })
+                    .expect("failed to recover shared memory segment");
+            }
+            libc::EDEADLK => panic!("shared memory segment is already locked by this thread"),
+            code => panic!(
+                "failed to lock shared memory segment: {}",
+                io::Error::from_raw_os_error(code)
+            ),
+        }
+        Guard {
+            segment,
+            _ptr: std::marker::PhantomData,
+        }
+    }
+
+    /// Locks the value. The segment has a single mutex, so readers exclude each other too.
+    pub fn read(&self) -> Guard<'_, T> {
+        self.lock()
+    }
+
+    pub fn write(&self) -> Guard<'_, T> {
+        self.lock()
+    }
+}
+
+impl<T: Shareable> Clone for SharedPtr<T> {
+    fn clone(&self) -> Self {
+        SharedPtr(self.0.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for SharedPtr<T>
+where
+    T: Shareable + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("SharedPtr").field(&*self.read()).finish()
+    }
+}
+
+/// Exclusive access to the value, across processes.
+pub struct Guard<'a, T: Shareable> {
+    segment: *mut Segment<T>,
+    _ptr: std::marker::PhantomData<&'a SharedPtr<T>>,
+}
+
+impl<T: Shareable> std::ops::Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the mutex is held for as long as the guard exists.
+        unsafe { &(*self.segment).value }
+    }
+}
+
+impl<T: Shareable> std::ops::DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the mutex is held for as long as the guard exists.
+        unsafe { &mut (*self.segment).value }
+    }
+}
+
+impl<T: Shareable> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: this guard locked the mutex.
+        unsafe { libc::pthread_mutex_unlock(std::ptr::addr_of_mut!((*self.segment).lock)) };
+    }
+}
+
+impl<T> std::fmt::Debug for Guard<'_, T>
+where
+    T: Shareable + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_shmem_segments_are_shared() {
+    let name = format!("shared-ptrs-test-{}", std::process::id());
+    let first = SharedPtr::open(&name, [0u64; 4]).unwrap();
+    let second = SharedPtr::open(&name, [9u64; 4]).unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|i| {
+            let ptr = if i % 2 == 0 { first.clone() } else { second.clone() };
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    ptr.write()[0] += 1;
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(*second.read(), [4000, 0, 0, 0]);
+
+    let mismatched = SharedPtr::open(&name, 0u8).unwrap_err();
+    assert_eq!(mismatched.kind(), io::ErrorKind::InvalidData);
+    unlink(&name).unwrap();
+}
+
+#[test]
+fn test_shmem_relock_and_owner_death() {
+    let name = format!("shared-ptrs-test-relock-{}", std::process::id());
+    let ptr = SharedPtr::open(&name, 0u32).unwrap();
+
+    let guard = ptr.write();
+    let relocked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(ptr.read())));
+    assert!(relocked.is_err());
+    drop(guard);
+
+    #[cfg(target_os = "linux")]
+    {
+        let owner = ptr.clone();
+        std::thread::spawn(move || {
+            let mut guard = owner.write();
+            *guard = 7;
+            std::mem::forget(guard);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(*ptr.read(), 7);
+        *ptr.write() += 1;
+        assert_eq!(*ptr.read(), 8);
+    }
+    unlink(&name).unwrap();
+}
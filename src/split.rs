@@ -0,0 +1,100 @@
+//! Single-writer, many-reader handles, and read-only views.
+
+macro_rules! define_split {
+    ($read_guard:ident) => {
+        /// The only handle to a value that can write it. See `SharedPtr::split`.
+        pub struct Writer<T: ?Sized>(SharedPtr<T>);
+
+        /// A read-only handle to a value owned by a `Writer`. Clones read the same value.
+        pub struct Reader<T: ?Sized>(SharedPtr<T>);
+
+        /// A handle that can only read, from `SharedPtr::read_only`.
+        pub type ReadOnlySharedPtr<T> = Reader<T>;
+
+        impl<T: ?Sized> SharedPtr<T> {
+            /// Splits the pointer into a `Writer`, which cannot be cloned, and a `Reader`, which
+            /// can. Single-writer code then fails to compile instead of relying on convention.
+            ///
+            /// Other `SharedPtr`s to the same value can still write, so split a pointer before
+            /// cloning it.
+            pub fn split(self) -> (Writer<T>, Reader<T>) {
+                let reader = Reader(self.clone());
+                (Writer(self), reader)
+            }
+
+            /// Returns a handle to the same value that can only read it, for subsystems that
+            /// should observe but not mutate.
+            pub fn read_only(&self) -> ReadOnlySharedPtr<T> {
+                Reader(self.clone())
+            }
+        }
+
+        impl<T: ?Sized> Writer<T> {
+            pub fn read(&self) -> $read_guard<'_, T> {
+                self.0.read()
+            }
+
+            #[track_caller]
+            pub fn write(&self) -> WriteGuard<'_, T> {
+                self.0.write()
+            }
+
+            pub fn reader(&self) -> Reader<T> {
+                Reader(self.0.clone())
+            }
+        }
+
+        impl<T: ?Sized> Reader<T> {
+            pub fn read(&self) -> $read_guard<'_, T> {
+                self.0.read()
+            }
+
+            /// Returns the read guard if it can be acquired without blocking.
+            pub fn try_read(&self) -> Option<$read_guard<'_, T>> {
+                self.0.try_read()
+            }
+        }
+
+        impl<T: ?Sized> Clone for Reader<T> {
+            fn clone(&self) -> Self {
+                Reader(self.0.clone())
+            }
+        }
+
+        impl<T> std::fmt::Debug for Writer<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("Writer").field(&self.0).finish()
+            }
+        }
+
+        impl<T> std::fmt::Debug for Reader<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("Reader").field(&self.0).finish()
+            }
+        }
+
+        #[test]
+        fn test_split() {
+            let (writer, reader) = SharedPtr::new(0u32).split();
+            let other = reader.clone();
+            *writer.write() += 1;
+            assert_eq!(*reader.read(), 1);
+            assert_eq!(*other.read(), 1);
+            assert_eq!(*writer.reader().read(), 1);
+        }
+
+        #[test]
+        fn test_read_only() {
+            let ptr = SharedPtr::new(0u32);
+            let view: ReadOnlySharedPtr<u32> = ptr.read_only();
+            *ptr.write() = 7;
+            assert_eq!(*view.clone().read(), 7);
+        }
+    };
+}
@@ -0,0 +1,54 @@
+//! Pointers that live directly in a `static`.
+
+macro_rules! define_static_shared {
+    ($lock:ident, $const_new:ident, $write_fn:ident, $read_guard:ident, $write_guard:ident) => {
+        /// A lock stored inline in a `static` rather than behind an allocation, built with
+        /// parking_lot's const constructors so it costs nothing at startup.
+        ///
+        /// It shares `SharedPtr`'s `read()`/`write()` surface but none of its per-pointer
+        /// extras: writes are not observed, audited or checked against invariants. Use a
+        /// `static_shared!` global for those.
+        pub struct StaticSharedPtr<T>($lock<T>);
+
+        impl<T> StaticSharedPtr<T> {
+            pub const fn new(init: T) -> Self {
+                StaticSharedPtr(crate::deps::parking_lot::$const_new(init))
+            }
+
+            pub fn read(&self) -> $read_guard<'_, T> {
+                read_lock(&self.0, crate::fairness::Fairness::default())
+            }
+
+            /// Returns the read guard if it can be acquired without blocking.
+            pub fn try_read(&self) -> Option<$read_guard<'_, T>> {
+                try_read_lock(&self.0, crate::fairness::Fairness::default())
+            }
+
+            pub fn write(&self) -> $write_guard<'_, T> {
+                self.0.$write_fn()
+            }
+        }
+
+        impl<T> std::fmt::Debug for StaticSharedPtr<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("StaticSharedPtr").field(&*self.read()).finish()
+            }
+        }
+
+        #[test]
+        fn test_static_shared_ptr() {
+            static COUNTER: StaticSharedPtr<u32> = StaticSharedPtr::new(0);
+
+            let threads: Vec<_> = (0..4)
+                .map(|_| std::thread::spawn(|| *COUNTER.write() += 1))
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(*COUNTER.read(), 4);
+        }
+    };
+}
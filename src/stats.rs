@@ -0,0 +1,280 @@
+//! Per-pointer lock acquisition statistics.
+//!
+//! Besides acquisitions and waits, the statistics include a histogram of how long `write()`
+//! guards were held. `SharedPtrBuilder::warn_long_holds` additionally reports every write guard
+//! held past a threshold to the hook set with `set_long_hold_hook`, which is the quickest way to
+//! find the critical sections behind latency spikes. Read guards are the lock's own guard types
+//! and are not timed.
+use std::panic::Location;
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::deps::parking_lot::{
+    const_rwlock,
+    Mutex,
+    RwLock,
+};
+
+const HOLD_BUCKETS: usize = 24;
+
+static LONG_HOLD_HOOK: RwLock<fn(&LongHold)> = const_rwlock(print_long_hold);
+
+/// Snapshot of how a pointer's lock has been used. See `SharedPtr::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    pub reads: u64,
+    pub writes: u64,
+    /// Acquisitions that could not take the lock immediately and had to wait.
+    pub contended_reads: u64,
+    pub contended_writes: u64,
+    /// Total time spent waiting in contended acquisitions.
+    pub wait_time: Duration,
+    pub write_holds: HoldHistogram,
+}
+
+/// How long guards were held. Bucket `i` counts holds under `2^i` microseconds that didn't fit
+/// an earlier bucket, and the last bucket also counts everything longer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HoldHistogram {
+    counts: [u64; HOLD_BUCKETS],
+}
+
+impl HoldHistogram {
+    /// The number of holds recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The upper bound and count of every bucket, shortest first.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts.iter().enumerate().map(|(bucket, &count)| (upper_bound(bucket), count))
+    }
+
+    /// The upper bound of the bucket holding the `quantile` (between 0 and 1) hold, e.g. `0.99`
+    /// for the 99th percentile. `None` if nothing has been recorded.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().find_map(|(bound, bucket)| {
+            seen += bucket;
+            (seen >= rank).then_some(bound)
+        })
+    }
+}
+
+fn bucket(held: Duration) -> usize {
+    let micros = held.as_micros().min(u64::MAX as u128) as u64;
+    let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+    bucket.min(HOLD_BUCKETS - 1)
+}
+
+fn upper_bound(bucket: usize) -> Duration {
+    if bucket == HOLD_BUCKETS - 1 {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << bucket)
+    }
+}
+
+/// A `write()` guard that was held longer than its pointer's threshold.
+#[derive(Debug, Clone)]
+pub struct LongHold {
+    /// The pointer's name, or its value type if it has none.
+    pub label: String,
+    /// Where the guard was taken.
+    pub location: &'static Location<'static>,
+    pub held: Duration,
+    pub threshold: Duration,
+}
+
+impl std::fmt::Display for LongHold {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} was write locked at {} for {:?}, longer than {:?}",
+            self.label, self.location, self.held, self.threshold
+        )
+    }
+}
+
+/// Replaces the function called with every long hold. The default prints the hold to stderr;
+/// set a hook to route it to a logger instead.
+pub fn set_long_hold_hook(hook: fn(&LongHold)) {
+    *LONG_HOLD_HOOK.write() = hook;
+}
+
+fn print_long_hold(hold: &LongHold) {
+    eprintln!("warning: {}", hold);
+}
+
+#[derive(Default)]
+struct Counters {
+    acquired: AtomicU64,
+    contended: AtomicU64,
+}
+
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    reads: Counters,
+    writes: Counters,
+    wait_nanos: AtomicU64,
+    write_holds: [AtomicU64; HOLD_BUCKETS],
+    // Only one writer holds the lock at a time, so a single start time is enough.
+    write_held_since: Mutex<Option<Instant>>,
+    long_hold: Option<Duration>,
+}
+
+impl StatsCounters {
+    /// Counters that also report write guards held for longer than `threshold`.
+    pub(crate) fn warning_after(threshold: Duration) -> Self {
+        StatsCounters {
+            long_hold: Some(threshold),
+            ..Default::default()
+        }
+    }
+
+    /// Acquires a guard, trying the non-blocking path first so contention can be detected and
+    /// the blocking wait timed.
+    pub(crate) fn acquire<G>(
+        &self,
+        write: bool,
+        try_lock: impl FnOnce() -> Option<G>,
+        lock: impl FnOnce() -> G,
+    ) -> G {
+        let counters = if write { &self.writes } else { &self.reads };
+        counters.acquired.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = try_lock() {
+            return guard;
+        }
+
+        counters.contended.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let guard = lock();
+        let waited = start.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+        self.wait_nanos.fetch_add(waited, Ordering::Relaxed);
+        guard
+    }
+
+    /// Called once the write lock is held.
+    pub(crate) fn begin_hold(&self) {
+        *self.write_held_since.lock() = Some(Instant::now());
+    }
+
+    /// Called just before the write lock is released.
+    pub(crate) fn end_hold(&self, label: &str, location: &'static Location<'static>) {
+        let held = match self.write_held_since.lock().take() {
+            Some(since) => since.elapsed(),
+            None => return,
+        };
+        self.write_holds[bucket(held)].fetch_add(1, Ordering::Relaxed);
+        if let Some(threshold) = self.long_hold.filter(|threshold| held > *threshold) {
+            let hook = *LONG_HOLD_HOOK.read();
+            hook(&LongHold {
+                label: label.to_owned(),
+                location,
+                held,
+                threshold,
+            });
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LockStats {
+        LockStats {
+            reads: self.reads.acquired.load(Ordering::Relaxed),
+            writes: self.writes.acquired.load(Ordering::Relaxed),
+            contended_reads: self.reads.contended.load(Ordering::Relaxed),
+            contended_writes: self.writes.contended.load(Ordering::Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            write_holds: HoldHistogram {
+                counts: std::array::from_fn(|bucket| {
+                    self.write_holds[bucket].load(Ordering::Relaxed)
+                }),
+            },
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_stats_detect_contention() {
+    use crate::arc_rwlock::SharedPtr;
+
+    let ptr = SharedPtr::new_with_stats(0u32);
+    assert_eq!(SharedPtr::new(0u32).stats(), None);
+
+    let guard = ptr.write();
+    let reader = {
+        let ptr = ptr.clone();
+        std::thread::spawn(move || *ptr.read())
+    };
+    // The reader counts the contention before it blocks, so it can't take the lock untimed.
+    while ptr.stats().unwrap().contended_reads == 0 {
+        std::thread::yield_now();
+    }
+    drop(guard);
+    reader.join().unwrap();
+    *ptr.write() += 1;
+
+    let stats = ptr.stats().unwrap();
+    assert_eq!(stats.reads, 1);
+    assert_eq!(stats.writes, 2);
+    assert_eq!(stats.contended_reads, 1);
+    assert_eq!(stats.contended_writes, 0);
+    assert!(stats.wait_time > Duration::ZERO);
+}
+
+#[test]
+fn test_hold_histogram() {
+    let stats = StatsCounters::default();
+    assert_eq!(stats.snapshot().write_holds.quantile(0.5), None);
+
+    for _ in 0..3 {
+        stats.begin_hold();
+        stats.end_hold("fast", Location::caller());
+    }
+    stats.begin_hold();
+    std::thread::sleep(Duration::from_millis(5));
+    stats.end_hold("slow", Location::caller());
+
+    let holds = stats.snapshot().write_holds;
+    assert_eq!(holds.count(), 4);
+    assert!(holds.quantile(0.5).unwrap() <= Duration::from_millis(1));
+    assert!(holds.quantile(1.0).unwrap() > Duration::from_millis(5));
+    assert_eq!(bucket(Duration::ZERO), 0);
+    assert_eq!(bucket(Duration::from_micros(3)), 2);
+    assert_eq!(bucket(Duration::from_secs(86400)), HOLD_BUCKETS - 1);
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+fn test_long_hold_warning() {
+    use crate::arc_mutex::SharedPtr;
+
+    static WARNINGS: Mutex<Vec<String>> = crate::deps::parking_lot::const_mutex(Vec::new());
+    set_long_hold_hook(|hold| WARNINGS.lock().push(hold.label.clone()));
+
+    let ptr = SharedPtr::builder(0u32)
+        .name("stats.test.slow")
+        .warn_long_holds(Duration::from_millis(5))
+        .build();
+    *ptr.write() += 1;
+    {
+        let _guard = ptr.write();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    set_long_hold_hook(print_long_hold);
+
+    assert_eq!(ptr.stats().unwrap().write_holds.count(), 2);
+    let warnings = WARNINGS.lock();
+    assert_eq!(warnings.iter().filter(|name| *name == "stats.test.slow").count(), 1);
+}
@@ -0,0 +1,128 @@
+//! Atomic updates spanning several pointers.
+//!
+//! Coordinating an invariant across two pointers means holding both write guards at once, and
+//! two threads taking them in opposite orders deadlock. `atomically` takes a tuple of pointers,
+//! possibly of different backends and value types, and always acquires them in address order,
+//! so any two transactions over overlapping sets of pointers are safe to run concurrently.
+//!
+//! ```ignore
+//! stm::atomically((&from, &to), |(mut from, mut to)| {
+//!     *from -= 10;
+//!     *to += 10;
+//! });
+//! ```
+
+/// Something that can be write-locked as part of a transaction. Implemented for references to
+/// every backend's `SharedPtr`.
+pub trait Lockable<'a> {
+    type Guard;
+
+    /// Identity of the underlying lock, used to order acquisition.
+    fn address(&self) -> *const ();
+
+    fn lock(self) -> Self::Guard;
+}
+
+/// A tuple of `Lockable`s acquired together.
+pub trait LockSet<'a> {
+    type Guards;
+
+    /// Acquires every lock in address order.
+    ///
+    /// # Panics
+    ///
+    /// If the same pointer appears twice, since that would deadlock.
+    fn lock_all(self) -> Self::Guards;
+}
+
+/// Write-locks every pointer in `locks` in a deadlock-free order and runs `f` with the guards.
+/// No other thread can observe the values until `f` returns and the guards are released.
+pub fn atomically<'a, L, R>(locks: L, f: impl FnOnce(L::Guards) -> R) -> R
+where
+    L: LockSet<'a>,
+{
+    f(locks.lock_all())
+}
+
+fn acquisition_order(addresses: &[*const ()]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..addresses.len()).collect();
+    order.sort_by_key(|&index| addresses[index]);
+    for pair in order.windows(2) {
+        assert!(
+            addresses[pair[0]] != addresses[pair[1]],
+            "the same pointer appears twice in a transaction"
+        );
+    }
+    order
+}
+
+macro_rules! impl_lock_set {
+    ($($index:tt $lock:ident),+) => {
+        impl<'a, $($lock),+> LockSet<'a> for ($($lock,)+)
+        where
+            $($lock: Lockable<'a>),+
+        {
+            type Guards = ($(<$lock as Lockable<'a>>::Guard,)+);
+
+            fn lock_all(self) -> Self::Guards {
+                let order = acquisition_order(&[$(self.$index.address()),+]);
+                let mut locks = ($(Some(self.$index),)+);
+                let mut guards = ($(None::<<$lock as Lockable<'a>>::Guard>,)+);
+                for index in order {
+                    match index {
+                        $($index => guards.$index = locks.$index.take().map(Lockable::lock),)+
+                        _ => unreachable!(),
+                    }
+                }
+                ($(guards.$index.expect("every lock is acquired"),)+)
+            }
+        }
+    };
+}
+
+impl_lock_set!(0 A);
+impl_lock_set!(0 A, 1 B);
+impl_lock_set!(0 A, 1 B, 2 C);
+impl_lock_set!(0 A, 1 B, 2 C, 3 D);
+impl_lock_set!(0 A, 1 B, 2 C, 3 D, 4 E);
+impl_lock_set!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+#[test]
+#[cfg(all(feature = "mutex", feature = "rwlock"))]
+fn test_atomically_opposite_orders() {
+    use crate::{
+        arc_mutex,
+        arc_rwlock,
+    };
+
+    let a = arc_mutex::SharedPtr::new(1000i64);
+    let b = arc_rwlock::SharedPtr::new(1000i64);
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let (a, b) = (a.clone(), b.clone());
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    if i % 2 == 0 {
+                        atomically((&a, &b), |(mut a, mut b)| {
+                            *a -= 1;
+                            *b += 1;
+                        });
+                    } else {
+                        atomically((&b, &a), |(mut b, mut a)| {
+                            *b -= 1;
+                            *a += 1;
+                        });
+                    }
+                    atomically((&a, &b), |(a, b)| assert_eq!(*a + *b, 2000));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*a.read(), 1000);
+    assert_eq!(*b.read(), 1000);
+}
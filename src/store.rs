@@ -0,0 +1,174 @@
+//! Reducer/dispatch state management.
+//!
+//! Each backend module has a `Store<T, A>` whose state can only be changed by dispatching an
+//! action `A` to a reducer `fn(&mut T, A)`. Middleware sits in front of the reducer and can
+//! inspect the state, log, rewrite, drop, or multiply actions before passing them on.
+
+/// The remainder of the middleware chain, ending in the reducer.
+pub trait Dispatch<T, A> {
+    /// The state as of this point in the chain.
+    fn state(&self) -> &T;
+
+    /// Passes `action` on to the next middleware, or to the reducer at the end of the chain.
+    fn dispatch(&mut self, action: A);
+}
+
+/// A hook run, in registration order, for every dispatched action.
+///
+/// Implemented for closures of the form `Fn(A, &mut dyn Dispatch<T, A>)`.
+pub trait Middleware<T, A> {
+    fn handle(&self, action: A, next: &mut dyn Dispatch<T, A>);
+}
+
+impl<T, A, F> Middleware<T, A> for F
+where
+    F: Fn(A, &mut dyn Dispatch<T, A>),
+{
+    fn handle(&self, action: A, next: &mut dyn Dispatch<T, A>) {
+        self(action, next)
+    }
+}
+
+pub(crate) struct Chain<'a, T, A, M: ?Sized> {
+    pub(crate) state: &'a mut T,
+    pub(crate) middleware: &'a [Box<M>],
+    pub(crate) reducer: fn(&mut T, A),
+}
+
+impl<T, A, M> Dispatch<T, A> for Chain<'_, T, A, M>
+where
+    M: ?Sized + Middleware<T, A>,
+{
+    fn state(&self) -> &T {
+        self.state
+    }
+
+    fn dispatch(&mut self, action: A) {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                action,
+                &mut Chain {
+                    state: &mut *self.state,
+                    middleware: rest,
+                    reducer: self.reducer,
+                },
+            ),
+            None => (self.reducer)(self.state, action),
+        }
+    }
+}
+
+macro_rules! define_store {
+    ($ptr:ident, $read_guard:ident $(, $bound:ident)*) => {
+        /// State that is only modified by dispatching actions through middleware to a reducer.
+        /// See the `store` module.
+        ///
+        /// Middleware runs while the state is locked, so it must not dispatch to the same store.
+        pub struct Store<T, A> {
+            state: SharedPtr<T>,
+            reducer: fn(&mut T, A),
+            middleware: $ptr<Vec<Box<dyn crate::store::Middleware<T, A> $(+ $bound)*>>>,
+        }
+
+        impl<T, A> Store<T, A> {
+            pub fn new(init: T, reducer: fn(&mut T, A)) -> Self {
+                Store {
+                    state: SharedPtr::new(init),
+                    reducer,
+                    middleware: $ptr::new(Vec::new()),
+                }
+            }
+
+            /// Appends a middleware to the chain.
+            ///
+            /// # Panics
+            ///
+            /// If the store has already been cloned.
+            pub fn with_middleware(
+                mut self,
+                middleware: impl crate::store::Middleware<T, A> $(+ $bound)* + 'static,
+            ) -> Self {
+                $ptr::get_mut(&mut self.middleware)
+                    .expect("middleware must be added before the store is cloned")
+                    .push(Box::new(middleware));
+                self
+            }
+
+            pub fn read(&self) -> $read_guard<'_, T> {
+                self.state.read()
+            }
+
+            /// Runs `action` through the middleware chain and the reducer under the write lock.
+            pub fn dispatch(&self, action: A) {
+                let mut state = self.state.write();
+                let mut chain = crate::store::Chain {
+                    state: &mut *state,
+                    middleware: &self.middleware,
+                    reducer: self.reducer,
+                };
+                crate::store::Dispatch::dispatch(&mut chain, action);
+            }
+        }
+
+        impl<T, A> Clone for Store<T, A> {
+            fn clone(&self) -> Self {
+                Store {
+                    state: self.state.clone(),
+                    reducer: self.reducer,
+                    middleware: self.middleware.clone(),
+                }
+            }
+        }
+
+        impl<T, A> std::fmt::Debug for Store<T, A>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_struct("Store")
+                    .field("state", &self.state)
+                    .field("middleware", &self.middleware.len())
+                    .finish()
+            }
+        }
+
+        #[test]
+        fn test_store_dispatch() {
+            use crate::store::Dispatch;
+
+            enum Action {
+                Add(i64),
+                Reset,
+            }
+
+            fn reducer(state: &mut i64, action: Action) {
+                match action {
+                    Action::Add(n) => *state += n,
+                    Action::Reset => *state = 0,
+                }
+            }
+
+            let store = Store::new(0i64, reducer)
+                .with_middleware(|action, next: &mut dyn Dispatch<i64, Action>| match action {
+                    Action::Reset if *next.state() > 100 => {}
+                    action => next.dispatch(action),
+                })
+                .with_middleware(|action, next: &mut dyn Dispatch<i64, Action>| match action {
+                    Action::Add(n) => {
+                        next.dispatch(Action::Add(n));
+                        next.dispatch(Action::Add(n));
+                    }
+                    action => next.dispatch(action),
+                });
+
+            store.dispatch(Action::Add(1));
+            assert_eq!(*store.read(), 2);
+            store.clone().dispatch(Action::Reset);
+            assert_eq!(*store.read(), 0);
+
+            store.dispatch(Action::Add(60));
+            store.dispatch(Action::Reset);
+            assert_eq!(*store.read(), 120);
+        }
+    };
+}
@@ -0,0 +1,181 @@
+//! Instrumented pointers for testing concurrency assumptions.
+//!
+//! `RecordingSharedPtr` has the `read()`/`write()` surface of `arc_rwlock::SharedPtr` but logs
+//! every acquisition with its caller, thread and time. Tests can inspect the log afterwards, or
+//! declare phases during which writes are a bug with `forbid_writes`.
+use std::panic::Location;
+use std::sync::Arc;
+use std::thread::ThreadId;
+use std::time::SystemTime;
+
+use crate::arc_rwlock::{
+    ReadGuard,
+    SharedPtr,
+    WriteGuard,
+};
+use crate::deps::parking_lot::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// One recorded acquisition.
+#[derive(Debug, Clone)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub location: &'static Location<'static>,
+    pub thread: ThreadId,
+    pub thread_name: Option<String>,
+    pub timestamp: SystemTime,
+}
+
+#[derive(Default)]
+struct Log {
+    accesses: Vec<Access>,
+    forbidden: Vec<&'static str>,
+}
+
+/// A pointer that records every `read()` and `write()`. Clones share the value and the log.
+pub struct RecordingSharedPtr<T> {
+    ptr: SharedPtr<T>,
+    log: Arc<Mutex<Log>>,
+}
+
+impl<T> RecordingSharedPtr<T> {
+    pub fn new(init: T) -> Self {
+        RecordingSharedPtr {
+            ptr: SharedPtr::new(init),
+            log: Default::default(),
+        }
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        self.record(AccessKind::Read, Location::caller());
+        self.ptr.read()
+    }
+
+    /// # Panics
+    ///
+    /// If a `forbid_writes` phase is active.
+    #[track_caller]
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        self.record(AccessKind::Write, Location::caller());
+        self.ptr.write()
+    }
+
+    /// Every acquisition so far, oldest first.
+    pub fn accesses(&self) -> Vec<Access> {
+        self.log.lock().accesses.clone()
+    }
+
+    /// The acquisitions of `kind` so far, oldest first.
+    pub fn accesses_of(&self, kind: AccessKind) -> Vec<Access> {
+        let log = self.log.lock();
+        log.accesses.iter().filter(|access| access.kind == kind).cloned().collect()
+    }
+
+    pub fn clear_accesses(&self) {
+        self.log.lock().accesses.clear();
+    }
+
+    /// Makes any `write()` through this pointer or its clones panic until the returned guard is
+    /// dropped. `phase` names the phase in the panic message.
+    pub fn forbid_writes(&self, phase: &'static str) -> ForbidWrites<'_> {
+        self.log.lock().forbidden.push(phase);
+        ForbidWrites {
+            log: &self.log,
+            phase,
+        }
+    }
+
+    fn record(&self, kind: AccessKind, location: &'static Location<'static>) {
+        let mut log = self.log.lock();
+        if let (AccessKind::Write, Some(phase)) = (kind, log.forbidden.last()) {
+            let phase = *phase;
+            drop(log);
+            panic!("write at {} during {} phase", location, phase);
+        }
+        let thread = std::thread::current();
+        log.accesses.push(Access {
+            kind,
+            location,
+            thread: thread.id(),
+            thread_name: thread.name().map(String::from),
+            timestamp: SystemTime::now(),
+        });
+    }
+}
+
+impl<T> Clone for RecordingSharedPtr<T> {
+    fn clone(&self) -> Self {
+        RecordingSharedPtr {
+            ptr: self.ptr.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for RecordingSharedPtr<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RecordingSharedPtr")
+            .field("value", &self.ptr)
+            .field("accesses", &self.log.lock().accesses.len())
+            .finish()
+    }
+}
+
+/// Ends a `forbid_writes` phase when dropped.
+pub struct ForbidWrites<'a> {
+    log: &'a Mutex<Log>,
+    phase: &'static str,
+}
+
+impl std::fmt::Debug for ForbidWrites<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("ForbidWrites").field(&self.phase).finish()
+    }
+}
+
+impl Drop for ForbidWrites<'_> {
+    fn drop(&mut self) {
+        let mut log = self.log.lock();
+        if let Some(index) = log.forbidden.iter().rposition(|phase| *phase == self.phase) {
+            log.forbidden.remove(index);
+        }
+    }
+}
+
+#[test]
+fn test_recording_shared_ptr() {
+    let ptr = RecordingSharedPtr::new(0u32);
+    let other = ptr.clone();
+    std::thread::Builder::new()
+        .name(String::from("writer"))
+        .spawn(move || *other.write() += 1)
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(*ptr.read(), 1);
+
+    let accesses = ptr.accesses();
+    assert_eq!(accesses.len(), 2);
+    assert_eq!(accesses[0].kind, AccessKind::Write);
+    assert_eq!(accesses[0].thread_name.as_deref(), Some("writer"));
+    assert_eq!(accesses[1].location.file(), file!());
+    assert_eq!(ptr.accesses_of(AccessKind::Read).len(), 1);
+
+    let render = ptr.forbid_writes("render");
+    assert_eq!(*ptr.read(), 1);
+    let write = std::panic::AssertUnwindSafe(|| *ptr.write() += 1);
+    let panic = std::panic::catch_unwind(write).unwrap_err();
+    assert!(panic.downcast_ref::<String>().unwrap().contains("during render phase"));
+    drop(render);
+    *ptr.write() += 1;
+    assert_eq!(*ptr.read(), 2);
+}
@@ -0,0 +1,127 @@
+//! Opt-in accounting of live pointer allocations, for finding cycles and forgotten clones.
+//!
+//! Once `enable()` is called, every pointer created afterwards is registered under the name of
+//! its value type until its last strong reference is dropped. `live_counts()` and `dump_live()`
+//! report what is still alive; a count that only grows points at a leak. Pointers created
+//! before `enable()` are never counted.
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{
+    AtomicU64,
+    AtomicU8,
+    Ordering,
+};
+
+use crate::deps::parking_lot::{
+    const_mutex,
+    Mutex,
+};
+
+const DISABLED: u8 = 0;
+const ENABLED: u8 = 1;
+const WITH_BACKTRACES: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(DISABLED);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static LIVE: Mutex<BTreeMap<u64, Instance>> = const_mutex(BTreeMap::new());
+
+struct Instance {
+    type_name: &'static str,
+    backtrace: Option<Backtrace>,
+}
+
+/// Starts tracking pointers created from now on. With `backtraces`, each registration also
+/// captures where the pointer was created, which is slow but shows up in `dump_live()`.
+pub fn enable(backtraces: bool) {
+    MODE.store(if backtraces { WITH_BACKTRACES } else { ENABLED }, Ordering::Relaxed);
+}
+
+/// Stops tracking new pointers. Pointers already tracked stay registered until dropped.
+pub fn disable() {
+    MODE.store(DISABLED, Ordering::Relaxed);
+}
+
+/// The number of tracked pointers still alive, by value type.
+pub fn live_counts() -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for instance in LIVE.lock().values() {
+        *counts.entry(instance.type_name).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Writes the live counts to `out`, followed by the creation backtrace of every live pointer if
+/// they were captured.
+pub fn dump_live(out: &mut impl io::Write) -> io::Result<()> {
+    let live = LIVE.lock();
+    writeln!(out, "{} live shared pointers:", live.len())?;
+    let mut counts = BTreeMap::new();
+    for instance in live.values() {
+        *counts.entry(instance.type_name).or_insert(0) += 1;
+    }
+    for (type_name, count) in counts {
+        writeln!(out, "{}: {}", type_name, count)?;
+    }
+    for (id, instance) in live.iter() {
+        if let Some(backtrace) = &instance.backtrace {
+            writeln!(out, "[{}] {} created at:\n{}", id, instance.type_name, backtrace)?;
+        }
+    }
+    Ok(())
+}
+
+/// Registration of one allocation, removed when the allocation is dropped.
+pub(crate) struct Tracked(u64);
+
+impl Tracked {
+    pub(crate) fn register<T: ?Sized>() -> Option<Self> {
+        let backtrace = match MODE.load(Ordering::Relaxed) {
+            DISABLED => return None,
+            ENABLED => None,
+            _ => Some(Backtrace::force_capture()),
+        };
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let type_name = std::any::type_name::<T>();
+        LIVE.lock().insert(id, Instance { type_name, backtrace });
+        Some(Tracked(id))
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        LIVE.lock().remove(&self.0);
+    }
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+fn test_tracker_counts_live_pointers() {
+    use crate::arc_mutex::SharedPtr;
+
+    #[derive(Debug)]
+    struct Leaky;
+
+    let name = std::any::type_name::<Leaky>();
+    let untracked = SharedPtr::new(Leaky);
+    enable(true);
+    let first = SharedPtr::new(Leaky);
+    let second = SharedPtr::new(Leaky);
+    disable();
+    let clone = first.clone();
+    assert_eq!(live_counts().get(name), Some(&2));
+
+    drop(first);
+    drop(second);
+    assert_eq!(live_counts().get(name), Some(&1));
+
+    let mut report = Vec::new();
+    dump_live(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.contains(&format!("{}: 1", name)));
+    assert!(report.contains("created at:"));
+
+    drop(clone);
+    drop(untracked);
+    assert_eq!(live_counts().get(name), None);
+}
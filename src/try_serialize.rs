@@ -0,0 +1,107 @@
+//! Non-blocking serialization.
+//!
+//! Serializing a pointer normally waits for its read lock, so snapshotting a state tree while
+//! another thread holds a write guard stalls the serializer (or deadlocks it, if the writer is
+//! waiting on the serializer). Inside `TrySerialize`, every pointer is serialized with a
+//! try-lock instead, and a contended pointer either fails the serialization or is written as a
+//! placeholder.
+use std::cell::Cell;
+
+use crate::deps::serde::ser::Error;
+use crate::deps::serde::{
+    Serialize,
+    Serializer,
+};
+
+thread_local! {
+    static MODE: Cell<Option<OnContention>> = const { Cell::new(None) };
+}
+
+/// What to do with a pointer that is locked by someone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnContention {
+    /// Fail the whole serialization with a custom error.
+    Error,
+    /// Serialize the pointer as unit (`null` in most formats).
+    Placeholder,
+}
+
+impl OnContention {
+    fn handle<S: Serializer>(
+        self,
+        serializer: S,
+        pointer: &str,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            OnContention::Error => Err(S::Error::custom(format_args!(
+                "{} is locked and cannot be serialized without blocking",
+                pointer
+            ))),
+            OnContention::Placeholder => serializer.serialize_unit(),
+        }
+    }
+}
+
+/// Serializes `value` without ever blocking on a pointer's lock.
+#[derive(Debug, Clone, Copy)]
+pub struct TrySerialize<T> {
+    pub value: T,
+    pub on_contention: OnContention,
+}
+
+impl<T> TrySerialize<T> {
+    pub fn new(value: T, on_contention: OnContention) -> Self {
+        TrySerialize {
+            value,
+            on_contention,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for TrySerialize<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let _reset = ResetMode(MODE.with(|mode| mode.replace(Some(self.on_contention))));
+        self.value.serialize(serializer)
+    }
+}
+
+/// Restores the previous mode on drop, even if serialization panics.
+struct ResetMode(Option<OnContention>);
+
+impl Drop for ResetMode {
+    fn drop(&mut self) {
+        MODE.with(|mode| mode.set(self.0));
+    }
+}
+
+/// The contention policy of the enclosing `TrySerialize`, if any.
+pub(crate) fn on_contention() -> Option<OnContention> {
+    MODE.with(Cell::get)
+}
+
+/// Serializes whatever the enclosing `TrySerialize` asks for in place of the locked `pointer`.
+pub(crate) fn contended<S: Serializer>(serializer: S, pointer: &str) -> Result<S::Ok, S::Error> {
+    on_contention()
+        .unwrap_or(OnContention::Error)
+        .handle(serializer, pointer)
+}
+
+#[test]
+#[cfg(feature = "mutex")]
+fn test_try_serialize_contended() {
+    use crate::arc_mutex::SharedPtr;
+    use crate::test_value::{
+        to_value,
+        Value,
+    };
+
+    let free = SharedPtr::new(1u32);
+    let held = SharedPtr::new(2u32);
+    let _guard = held.write();
+
+    let error = to_value(&TrySerialize::new((&free, &held), OnContention::Error)).unwrap_err();
+    assert!(error.to_string().contains("is locked"));
+
+    let value = to_value(&TrySerialize::new((&free, &held), OnContention::Placeholder)).unwrap();
+    assert_eq!(value, Value::Seq(vec![Value::U64(1), Value::Unit]));
+}
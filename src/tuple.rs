@@ -0,0 +1,111 @@
+//! Several values under one lock, accessed element by element.
+
+macro_rules! impl_shared_tuple {
+    ($tuple:ty; $($index:tt $read:ident $write:ident $elem:ident),+) => {
+        impl<$($elem),+> SharedTuple<$tuple> {
+            $(
+                #[doc = concat!(
+                    "Read-locks the tuple and returns a guard onto element ",
+                    stringify!($index),
+                    "."
+                )]
+                pub fn $read(&self) -> FieldRef<'_, $tuple, $elem> {
+                    self.0.read_field(|tuple| &tuple.$index)
+                }
+
+                #[doc = concat!(
+                    "Write-locks the tuple and returns a guard onto element ",
+                    stringify!($index),
+                    "."
+                )]
+                #[track_caller]
+                pub fn $write(&self) -> FieldRefMut<'_, $tuple, $elem> {
+                    self.0.write_field(|tuple| &mut tuple.$index)
+                }
+            )+
+        }
+    };
+}
+
+macro_rules! define_shared_tuple {
+    () => {
+        /// A tuple of tightly coupled values guarded by a single lock, with guards onto
+        /// individual elements. Unlike a tuple of pointers, updating several elements never
+        /// needs several locks; lock the whole tuple through `Deref` for that.
+        ///
+        /// Clones share the tuple.
+        pub struct SharedTuple<T>(SharedPtr<T>);
+
+        impl<T> SharedTuple<T> {
+            pub fn new(init: T) -> Self {
+                SharedTuple(SharedPtr::new(init))
+            }
+        }
+
+        impl_shared_tuple!((A, B); 0 read_0 write_0 A, 1 read_1 write_1 B);
+        impl_shared_tuple!((A, B, C); 0 read_0 write_0 A, 1 read_1 write_1 B, 2 read_2 write_2 C);
+        impl_shared_tuple!(
+            (A, B, C, D);
+            0 read_0 write_0 A,
+            1 read_1 write_1 B,
+            2 read_2 write_2 C,
+            3 read_3 write_3 D
+        );
+        impl_shared_tuple!(
+            (A, B, C, D, E);
+            0 read_0 write_0 A,
+            1 read_1 write_1 B,
+            2 read_2 write_2 C,
+            3 read_3 write_3 D,
+            4 read_4 write_4 E
+        );
+        impl_shared_tuple!(
+            (A, B, C, D, E, F);
+            0 read_0 write_0 A,
+            1 read_1 write_1 B,
+            2 read_2 write_2 C,
+            3 read_3 write_3 D,
+            4 read_4 write_4 E,
+            5 read_5 write_5 F
+        );
+
+        impl<T> std::ops::Deref for SharedTuple<T> {
+            type Target = SharedPtr<T>;
+
+            fn deref(&self) -> &SharedPtr<T> {
+                &self.0
+            }
+        }
+
+        impl<T> Clone for SharedTuple<T> {
+            fn clone(&self) -> Self {
+                SharedTuple(self.0.clone())
+            }
+        }
+
+        impl<T> std::fmt::Debug for SharedTuple<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_tuple("SharedTuple").field(&*self.0.read()).finish()
+            }
+        }
+
+        #[test]
+        fn test_shared_tuple() {
+            let state = SharedTuple::new((0u32, String::from("idle"), vec![1u8]));
+            *state.write_0() += 1;
+            state.write_1().push_str("-ish");
+            assert_eq!(*state.read_0(), 1);
+            assert_eq!(*state.read_1(), "idle-ish");
+            assert_eq!(state.read_2().len(), 1);
+
+            let mut all = state.write();
+            all.0 += 1;
+            all.2.clear();
+            drop(all);
+            assert_eq!(*state.clone().read_0(), 2);
+        }
+    };
+}
@@ -0,0 +1,119 @@
+//! Pointers that can be handed out before their value exists.
+
+macro_rules! define_shared_uninit {
+    () => {
+        /// A pointer whose value is provided later with `init()`, so handles can be wired into
+        /// components that are built before the value they point to. Until then `read()` and
+        /// `write()` return `None`.
+        ///
+        /// Clones share the (eventual) value.
+        pub struct SharedUninit<T>(SharedPtr<Option<T>>);
+
+        /// A weak handle to a `SharedUninit`.
+        pub struct WeakUninit<T>(WeakPtr<Option<T>>);
+
+        impl<T> SharedUninit<T> {
+            pub fn new() -> Self {
+                SharedUninit(SharedPtr::new(None))
+            }
+
+            /// Provides the value, handing it back if one was already provided.
+            #[track_caller]
+            pub fn init(&self, value: T) -> Result<(), T> {
+                let mut slot = self.0.write();
+                match *slot {
+                    Some(_) => Err(value),
+                    None => {
+                        *slot = Some(value);
+                        Ok(())
+                    }
+                }
+            }
+
+            pub fn is_init(&self) -> bool {
+                self.0.read().is_some()
+            }
+
+            pub fn read(&self) -> Option<FieldRef<'_, Option<T>, T>> {
+                let slot = self.0.read();
+                if slot.is_none() {
+                    return None;
+                }
+                Some(crate::field::FieldGuard::new(slot).map(|v| v.as_ref().unwrap()))
+            }
+
+            #[track_caller]
+            pub fn write(&self) -> Option<FieldRefMut<'_, Option<T>, T>> {
+                let slot = self.0.write();
+                if slot.is_none() {
+                    return None;
+                }
+                Some(crate::field::FieldGuardMut::new(slot).map(|v| v.as_mut().unwrap()))
+            }
+
+            pub fn downgrade(&self) -> WeakUninit<T> {
+                WeakUninit(WeakPtr::downgrade(&self.0))
+            }
+        }
+
+        impl<T> WeakUninit<T> {
+            pub fn upgrade(&self) -> Option<SharedUninit<T>> {
+                self.0.upgrade().map(SharedUninit)
+            }
+        }
+
+        impl<T> Clone for SharedUninit<T> {
+            fn clone(&self) -> Self {
+                SharedUninit(self.0.clone())
+            }
+        }
+
+        impl<T> Default for SharedUninit<T> {
+            fn default() -> Self {
+                SharedUninit::new()
+            }
+        }
+
+        impl<T> std::fmt::Debug for SharedUninit<T>
+        where
+            T: std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match &*self.0.read() {
+                    Some(value) => f.debug_tuple("SharedUninit").field(value).finish(),
+                    None => f.write_str("SharedUninit(<uninit>)"),
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for WeakUninit<T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("WeakUninit")
+            }
+        }
+
+        #[test]
+        fn test_shared_uninit_wiring() {
+            struct Parent {
+                child: SharedPtr<Child>,
+            }
+
+            struct Child {
+                parent: WeakUninit<Parent>,
+            }
+
+            let parent = SharedUninit::new();
+            let child = SharedPtr::new(Child {
+                parent: parent.downgrade(),
+            });
+            assert!(child.read().parent.upgrade().unwrap().read().is_none());
+
+            assert!(parent.init(Parent { child }).is_ok());
+            assert!(parent.is_init());
+            let child = parent.read().unwrap().child.clone();
+            let back = child.read().parent.upgrade().unwrap();
+            assert!(back.write().is_some());
+            assert!(parent.init(Parent { child }).is_err());
+        }
+    };
+}